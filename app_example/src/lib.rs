@@ -1,47 +1,97 @@
 use faisca::{
-    renderer::{Renderer, RendererError},
-    AppMessage, SafeCString, WindowEvent, WindowInstance, WindowMessenger,
+    renderer::{Point2DColorRGBVertex, Renderer, RendererError, Vector2, Vector3},
+    request, AppMessage, SafeCString, WindowEvent, WindowInstance, WindowMessenger,
 };
+use std::sync::atomic::{AtomicU32, Ordering};
 
-fn entry(w: WindowInstance, messenger: WindowMessenger) {
+/// How many times the library was reloaded, the only state we hand over.
+static RELOAD_COUNT: AtomicU32 = AtomicU32::new(0);
+
+fn entry(w: WindowInstance, mut messenger: WindowMessenger) {
     env_logger::init();
     log::info!("Log enabled");
-    messenger.send(
-        w,
-        &AppMessage::SetWindowTitle(SafeCString::allocate_from_str("VkTut").unwrap()),
-    );
-
-    let mut win_extent_w = 800;
-    let mut win_extent_h = 450;
 
-    messenger.send(
-        w,
-        &AppMessage::SetWindowSize {
-            width: win_extent_w,
-            height: win_extent_h,
-        },
-    );
+    let reload_count = RELOAD_COUNT.load(Ordering::Relaxed);
+    let (mut win_extent_w, mut win_extent_h) = if reload_count == 0 {
+        messenger.send(
+            w,
+            &AppMessage::SetWindowTitle(SafeCString::allocate_from_str("VkTut").unwrap()),
+        );
+        messenger.send(
+            w,
+            &AppMessage::SetWindowSize {
+                width: 800,
+                height: 450,
+            },
+        );
+        messenger.send(w, &AppMessage::SetWindowResizable(true));
+        messenger.set_window_icon(w, ICON_SIZE, ICON_SIZE, &icon_pixels());
+        (800, 450)
+    } else {
+        // The window is still there, just as we left it
+        log::info!("Reloaded {reload_count} time(s)");
+        let extent = messenger
+            .request(w, request::QueryViewportExtents)
+            .wait()
+            .unwrap();
+        (extent.width, extent.height)
+    };
 
-    messenger.send(w, &AppMessage::SetWindowResizable(true));
+    // Dragging the window border sends lots of resizes, we only care about
+    // the last one
+    messenger.set_coalesce_resizes(true);
 
     let mut renderer = Renderer::new(w, &messenger).unwrap_or_else(|e| {
         log::error!("Failed to create renderer: {e}");
         std::process::abort();
     });
+    renderer
+        .create_mesh(&QUAD_VERTICES, &QUAD_INDICES)
+        .unwrap_or_else(|e| {
+            log::error!("Failed to create mesh: {e}");
+            std::process::abort();
+        });
 
     'app_loop: loop {
-        if let Some((_msg_win, win_event)) = messenger.try_recv() {
+        for (_msg_win, win_event) in messenger.drain() {
             match win_event {
                 WindowEvent::Quit => {
                     log::info!("Quitting application");
                     break 'app_loop;
                 }
-                WindowEvent::WindowResize { w, h } => {
-                    log::debug!("Window resize event received: {w}, {h}");
-                    win_extent_w = w;
-                    win_extent_h = h;
+                WindowEvent::ReloadRequested => {
+                    log::info!("Returning for reload");
+                    break 'app_loop;
+                }
+                WindowEvent::WindowResize {
+                    w,
+                    h,
+                    pixel_w,
+                    pixel_h,
+                } => {
+                    log::debug!(
+                        "Window resize event received: {w}, {h} ({pixel_w}, {pixel_h} pixels)"
+                    );
+                    win_extent_w = pixel_w;
+                    win_extent_h = pixel_h;
                     renderer.window_resized(win_extent_w, win_extent_h).unwrap();
                 }
+                WindowEvent::ScaleFactorChanged { scale } => {
+                    log::debug!("Window scale factor changed: {scale}");
+                }
+                WindowEvent::DropFile { path } => {
+                    log::info!("File dropped: {}", path.as_cstr().to_string_lossy());
+                }
+                WindowEvent::Minimized => renderer.window_minimized(),
+                WindowEvent::Restored => renderer.window_restored().unwrap(),
+                WindowEvent::SurfaceLost => renderer.surface_lost().unwrap(),
+                WindowEvent::SurfaceRecreated => renderer.surface_recreated(&messenger).unwrap(),
+                WindowEvent::KeyDown {
+                    scancode: SCANCODE_F12,
+                    repeat: false,
+                    ..
+                } => renderer.capture_next_frame(),
+                _ => (),
             }
         }
 
@@ -53,12 +103,62 @@ fn entry(w: WindowInstance, messenger: WindowMessenger) {
             }
             Err(e) => panic!("{e}"),
         }
+
+        if let Some(frame) = renderer.take_captured_frame() {
+            match frame.save_png(SCREENSHOT_PATH) {
+                Ok(()) => log::info!("Screenshot saved to {SCREENSHOT_PATH}"),
+                Err(e) => log::error!("Failed to save screenshot: {e}"),
+            }
+        }
     }
 }
 
+/// Takes a screenshot.
+const SCANCODE_F12: u32 = 69;
+const SCREENSHOT_PATH: &str = "screenshot.png";
+
+#[rustfmt::skip]
+const QUAD_VERTICES: [Point2DColorRGBVertex; 4] = [
+    Point2DColorRGBVertex { point: Vector2([ 0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
+    Point2DColorRGBVertex { point: Vector2([ 0.5,  0.5]), color: Vector3([ 0.0,  1.0,  0.0]) },
+    Point2DColorRGBVertex { point: Vector2([-0.5,  0.5]), color: Vector3([ 0.0,  0.0,  1.0]) },
+    Point2DColorRGBVertex { point: Vector2([-0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
+];
+const QUAD_INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+const ICON_SIZE: u32 = 32;
+
+/// An orange diamond, standing in for a branded icon.
+fn icon_pixels() -> Vec<u8> {
+    let center = ICON_SIZE as i32 / 2;
+    (0..ICON_SIZE as i32)
+        .flat_map(|y| (0..ICON_SIZE as i32).map(move |x| (x, y)))
+        .flat_map(|(x, y)| {
+            if (x - center).abs() + (y - center).abs() < center {
+                [255, 140, 0, 255]
+            } else {
+                [0, 0, 0, 0]
+            }
+        })
+        .collect()
+}
+
 fn process() -> i32 {
     0
 }
 
+fn save_state() -> Vec<u8> {
+    (RELOAD_COUNT.load(Ordering::Relaxed) + 1)
+        .to_le_bytes()
+        .to_vec()
+}
+
+fn load_state(state: &[u8]) {
+    if let Ok(bytes) = state.try_into() {
+        RELOAD_COUNT.store(u32::from_le_bytes(bytes), Ordering::Relaxed);
+    }
+}
+
 faisca::app_entry!(entry);
 faisca::app_process!(process);
+faisca::app_state!(save_state, load_state);
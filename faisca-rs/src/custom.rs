@@ -0,0 +1,125 @@
+use crate::ffi::WindowInstance;
+use std::sync::{Arc, PoisonError, RwLock};
+
+/// A message Faisca knows nothing about, agreed on by the app and a host
+/// plugin (like an achievements service). It travels as the payload of an
+/// [AppMessage::Custom](crate::AppMessage::Custom) or a
+/// [WindowMessage::Custom](crate::WindowMessage::Custom), and is told apart
+/// from the others by its `KIND`, which must be unique among the custom
+/// messages of the host.
+///
+/// Send it with [WindowMessenger::send_custom](crate::WindowMessenger::send_custom),
+/// and receive it with [register_handler].
+pub trait CustomMessage: Sized {
+    const KIND: u32;
+
+    fn encode(&self) -> Vec<u8>;
+
+    /// Returns `None` if `payload` is not a valid message of this kind.
+    fn decode(payload: &[u8]) -> Option<Self>;
+}
+
+/// Returns whether the payload was a valid message.
+type CustomHandler = Arc<dyn Fn(WindowInstance, &[u8]) -> bool + Send + Sync>;
+
+static CUSTOM_HANDLERS: RwLock<Vec<(u32, CustomHandler)>> = RwLock::new(Vec::new());
+
+/// Makes `handler` handle the custom messages of type `M` the host sends,
+/// replacing the handler `M::KIND` had. It runs on the host thread.
+pub fn register_handler<M: CustomMessage>(
+    handler: impl Fn(WindowInstance, M) + Send + Sync + 'static,
+) {
+    let handler: CustomHandler = Arc::new(move |w, payload| match M::decode(payload) {
+        Some(msg) => {
+            handler(w, msg);
+            true
+        }
+        None => false,
+    });
+
+    let mut handlers = CUSTOM_HANDLERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    handlers.retain(|(kind, _)| *kind != M::KIND);
+    handlers.push((M::KIND, handler));
+}
+
+/// Stops handling the custom messages of `kind`.
+pub fn unregister_handler(kind: u32) {
+    CUSTOM_HANDLERS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .retain(|(handler_kind, _)| *handler_kind != kind);
+}
+
+/// Hands a custom message from the host to its handler. Returns `false` if
+/// there is no handler for `kind`, or if the payload is not valid.
+pub(crate) fn dispatch(w: WindowInstance, kind: u32, payload: &[u8]) -> bool {
+    // Not holding the lock while the handler runs, so that it may register
+    // handlers of its own
+    let handler = CUSTOM_HANDLERS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .find(|(handler_kind, _)| *handler_kind == kind)
+        .map(|(_, handler)| Arc::clone(handler));
+
+    match handler {
+        Some(handler) => {
+            let handled = handler(w, payload);
+            if !handled {
+                log::warn!("Dropping custom message of kind {kind}: invalid payload");
+            }
+            handled
+        }
+        None => {
+            log::warn!("Dropping custom message of kind {kind}: no handler registered");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// An achievement, sent as its id.
+    #[derive(Debug, PartialEq)]
+    struct Unlock(u32);
+
+    impl CustomMessage for Unlock {
+        // The registry is shared by every test, so the kinds must be unique
+        const KIND: u32 = 0xC057_0001;
+
+        fn encode(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn decode(payload: &[u8]) -> Option<Self> {
+            Some(Self(u32::from_le_bytes(payload.try_into().ok()?)))
+        }
+    }
+
+    #[test]
+    fn custom_dispatch_test() {
+        let w = WindowInstance::from_raw(1);
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let handler_received = Arc::clone(&received);
+        register_handler(move |w, msg: Unlock| {
+            handler_received.lock().unwrap().push((w, msg));
+        });
+
+        assert!(dispatch(w, Unlock::KIND, &Unlock(7).encode()));
+        assert!(!dispatch(w, Unlock::KIND, &[1, 2]));
+        assert_eq!(*received.lock().unwrap(), [(w, Unlock(7))]);
+
+        // A kind nobody registered
+        assert!(!dispatch(w, Unlock::KIND + 1, &Unlock(8).encode()));
+
+        unregister_handler(Unlock::KIND);
+        assert!(!dispatch(w, Unlock::KIND, &Unlock(9).encode()));
+        assert_eq!(received.lock().unwrap().len(), 1);
+    }
+}
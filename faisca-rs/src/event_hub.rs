@@ -0,0 +1,278 @@
+use crate::{MessageWindowFn, WChanMsg, WindowEvent, WindowInstance};
+use std::{
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Mutex, PoisonError},
+    time::Duration,
+};
+
+type EventFilter = Box<dyn Fn(WindowInstance, &WindowEvent) -> bool + Send>;
+
+struct Subscription {
+    id: u64,
+    filter: EventFilter,
+    sender: mpsc::Sender<WChanMsg>,
+}
+
+/// Where the events of a host go. There is one for each host the app talks
+/// to, and they are never removed, so a host may keep sending events to it
+/// for as long as it lives.
+struct EventHub {
+    /// The address of the host's `MessageWindowFn`.
+    host: usize,
+    subscriptions: Vec<Subscription>,
+}
+
+struct HubRegistry {
+    hubs: Vec<EventHub>,
+    next_subscription: u64,
+}
+
+// Hosts hold the index of a hub plus one (zero being no hub at all), never a
+// pointer, so an event arriving at any time is safe to handle.
+static HUB_REGISTRY: Mutex<HubRegistry> = Mutex::new(HubRegistry {
+    hubs: Vec::new(),
+    next_subscription: 0,
+});
+
+/// Returns the handle of the hub for the host, creating it if needed.
+pub(crate) fn hub_for_host(host: MessageWindowFn) -> usize {
+    let mut registry = HUB_REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let host = host as usize;
+
+    let index = match registry.hubs.iter().position(|hub| hub.host == host) {
+        Some(index) => index,
+        None => {
+            registry.hubs.push(EventHub {
+                host,
+                subscriptions: Vec::new(),
+            });
+            registry.hubs.len() - 1
+        }
+    };
+    index + 1
+}
+
+pub(crate) fn subscribe(hub: usize, filter: EventFilter) -> EventSubscriber {
+    let (sender, receiver) = mpsc::channel();
+
+    let mut registry = HUB_REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let id = registry.next_subscription;
+    registry.next_subscription += 1;
+    registry.hubs[hub - 1]
+        .subscriptions
+        .push(Subscription { id, filter, sender });
+
+    EventSubscriber {
+        hub,
+        id,
+        receiver,
+        coalesce_resizes: false,
+        pending: Cell::new(None),
+    }
+}
+
+/// Hands a copy of the event to every subscriber of the hub whose filter
+/// accepts it. Returns `false` if there is no such hub.
+pub(crate) fn publish(hub: usize, w: WindowInstance, event: &WindowEvent) -> bool {
+    let mut registry = HUB_REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let Some(hub) = hub
+        .checked_sub(1)
+        .and_then(|index| registry.hubs.get_mut(index))
+    else {
+        return false;
+    };
+
+    for subscription in &hub.subscriptions {
+        // A panicking filter must not keep the event from the others, nor
+        // unwind into the host
+        let accepted = panic::catch_unwind(AssertUnwindSafe(|| (subscription.filter)(w, event)))
+            .unwrap_or_else(|_| {
+                log::error!("An event filter panicked, dropping the event for its subscriber");
+                false
+            });
+        if accepted {
+            // The subscriber may be gone already, in which case it is
+            // about to unsubscribe
+            let _ = subscription.sender.send((w, event.clone()));
+        }
+    }
+    true
+}
+
+/// Receives the events of a host, or the ones its filter accepts. See
+/// [WindowMessenger::subscribe](crate::WindowMessenger::subscribe).
+///
+/// It stops receiving events once dropped.
+pub struct EventSubscriber {
+    hub: usize,
+    id: u64,
+    receiver: mpsc::Receiver<WChanMsg>,
+    coalesce_resizes: bool,
+    /// An event taken from the channel while coalescing, to be handed out
+    /// before any other.
+    pending: Cell<Option<WChanMsg>>,
+}
+
+impl EventSubscriber {
+    pub fn try_recv(&self) -> Option<WChanMsg> {
+        let msg = match self.pending.take() {
+            Some(msg) => msg,
+            None => self.receiver.try_recv().ok()?,
+        };
+        Some(self.coalesce(msg))
+    }
+
+    /// Blocks until an event arrives, or until `timeout` elapses. Lets an
+    /// app that has nothing to draw sleep until there is input.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<WChanMsg> {
+        let msg = match self.pending.take() {
+            Some(msg) => msg,
+            None => self.receiver.recv_timeout(timeout).ok()?,
+        };
+        Some(self.coalesce(msg))
+    }
+
+    /// Returns every event that already arrived, without blocking.
+    pub fn drain(&self) -> impl Iterator<Item = WChanMsg> + '_ {
+        std::iter::from_fn(|| self.try_recv())
+    }
+
+    /// When enabled, consecutive [WindowResize](WindowEvent::WindowResize)
+    /// events for the same window that already arrived are collapsed into
+    /// the last one, so that a burst of them only recreates the swapchain
+    /// once. Disabled by default.
+    pub fn set_coalesce_resizes(&mut self, enabled: bool) {
+        self.coalesce_resizes = enabled;
+    }
+
+    fn coalesce(&self, mut msg: WChanMsg) -> WChanMsg {
+        if !self.coalesce_resizes {
+            return msg;
+        }
+
+        while matches!(msg.1, WindowEvent::WindowResize { .. }) {
+            match self.receiver.try_recv() {
+                Ok(next)
+                    if next.0 == msg.0 && matches!(next.1, WindowEvent::WindowResize { .. }) =>
+                {
+                    msg = next;
+                }
+                Ok(next) => {
+                    self.pending.set(Some(next));
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        msg
+    }
+}
+
+impl Drop for EventSubscriber {
+    fn drop(&mut self) {
+        let mut registry = HUB_REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+        registry.hubs[self.hub - 1]
+            .subscriptions
+            .retain(|subscription| subscription.id != self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppMessage;
+
+    unsafe extern "C" fn test_host(_w: WindowInstance, _msg: *const AppMessage) -> u32 {
+        1
+    }
+
+    #[test]
+    fn event_hub_test() {
+        let hub = hub_for_host(test_host);
+        assert_eq!(hub_for_host(test_host), hub);
+
+        let mut everything = subscribe(hub, Box::new(|_, _| true));
+        everything.set_coalesce_resizes(true);
+        let quits = subscribe(hub, Box::new(|_, event| *event == WindowEvent::Quit));
+
+        let (w1, w2) = (WindowInstance::from_raw(1), WindowInstance::from_raw(2));
+        let resize = |w| WindowEvent::WindowResize {
+            w,
+            h: w,
+            pixel_w: w,
+            pixel_h: w,
+        };
+        for (w, event) in [
+            (w1, resize(100)),
+            (w1, resize(200)),
+            (w2, resize(300)),
+            (w2, resize(400)),
+            (w2, WindowEvent::Exposed),
+            (w2, resize(500)),
+            (w1, WindowEvent::Quit),
+        ] {
+            assert!(publish(hub, w, &event));
+        }
+
+        assert_eq!(
+            everything.drain().collect::<Vec<_>>(),
+            vec![
+                (w1, resize(200)),
+                (w2, resize(400)),
+                (w2, WindowEvent::Exposed),
+                (w2, resize(500)),
+                (w1, WindowEvent::Quit),
+            ]
+        );
+        assert_eq!(everything.recv_timeout(Duration::from_millis(10)), None);
+        assert_eq!(
+            quits.drain().collect::<Vec<_>>(),
+            vec![(w1, WindowEvent::Quit)]
+        );
+
+        drop(quits);
+        assert!(publish(hub, w1, &WindowEvent::Quit));
+        assert_eq!(everything.try_recv(), Some((w1, WindowEvent::Quit)));
+
+        // A handle the app never gave out
+        assert!(!publish(0, w1, &WindowEvent::Quit));
+    }
+
+    unsafe extern "C" fn panicking_filter_host(_w: WindowInstance, _msg: *const AppMessage) -> u32 {
+        1
+    }
+
+    #[test]
+    fn panicking_filter_test() {
+        let hub = hub_for_host(panicking_filter_host);
+        let w = WindowInstance::from_raw(1);
+
+        let everything = subscribe(hub, Box::new(|_, _| true));
+        let panicking = subscribe(
+            hub,
+            Box::new(|_, event| {
+                assert_ne!(*event, WindowEvent::Quit);
+                true
+            }),
+        );
+
+        assert!(publish(hub, w, &WindowEvent::Quit));
+        assert!(publish(hub, w, &WindowEvent::Exposed));
+        assert_eq!(
+            everything.drain().collect::<Vec<_>>(),
+            vec![(w, WindowEvent::Quit), (w, WindowEvent::Exposed)]
+        );
+        assert_eq!(
+            panicking.drain().collect::<Vec<_>>(),
+            vec![(w, WindowEvent::Exposed)]
+        );
+
+        // The hub is still usable afterwards
+        drop(panicking);
+        let later = subscribe(hub, Box::new(|_, _| true));
+        assert!(publish(hub, w, &WindowEvent::Quit));
+        assert_eq!(later.try_recv(), Some((w, WindowEvent::Quit)));
+        assert_eq!(everything.try_recv(), Some((w, WindowEvent::Quit)));
+    }
+}
@@ -15,6 +15,29 @@ impl SafeCString {
         unsafe { CStr::from_ptr(self.0) }
     }
 }
+// Hosts send strings they own along with events, which we copy through this
+// before the host frees them.
+impl Clone for SafeCString {
+    fn clone(&self) -> Self {
+        let string = self.as_cstr().to_owned();
+        let pointer = string.as_ptr();
+        std::mem::forget(string);
+        SafeCString(pointer)
+    }
+}
+impl std::fmt::Debug for SafeCString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_cstr().fmt(f)
+    }
+}
+impl PartialEq for SafeCString {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_cstr() == other.as_cstr()
+    }
+}
+// The string is owned and never mutated, so it can be shared between threads
+unsafe impl Send for SafeCString {}
+unsafe impl Sync for SafeCString {}
 impl Drop for SafeCString {
     fn drop(&mut self) {
         unsafe {
@@ -23,19 +46,95 @@ impl Drop for SafeCString {
     }
 }
 
-#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C, u8)]
 pub enum Fullscreen {
     No = 0,
-    Real = 1,
+    /// Exclusive fullscreen on the display at index `display` of the
+    /// [QueryDisplays](crate::request::QueryDisplays) answer, switching it to
+    /// `mode`, which must be one of the modes the display reported.
+    Real {
+        display: u32,
+        mode: DisplayMode,
+    } = 1,
     BorderlessWindow = 2,
 }
 
+/// A display mode. `refresh_rate` is in Hz, and is zero if unknown.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: u32,
+}
+
+/// The area a display covers, in the space spanning every display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DisplayBounds {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How hosts answer [QueryDisplays](AppMessage::QueryDisplays).
+#[repr(C)]
+pub struct RawDisplayList {
+    pub displays: *const RawDisplay,
+    pub count: usize,
+}
+
+#[repr(C)]
+pub struct RawDisplay {
+    /// A null terminated UTF-8 string, may be null.
+    pub name: *const i8,
+    pub bounds: DisplayBounds,
+    pub current_mode: DisplayMode,
+    pub modes: *const DisplayMode,
+    pub mode_count: usize,
+}
+
+/// The system cursor shapes. The values match SDL's `SDL_SystemCursor`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SystemCursor {
+    #[default]
+    Arrow = 0,
+    IBeam = 1,
+    Wait = 2,
+    Crosshair = 3,
+    /// Small wait cursor, or [Wait](SystemCursor::Wait) if not available.
+    WaitArrow = 4,
+    SizeNwSe = 5,
+    SizeNeSw = 6,
+    SizeWE = 7,
+    SizeNS = 8,
+    SizeAll = 9,
+    /// Slashed circle or crossbones.
+    No = 10,
+    Hand = 11,
+}
+
+/// The app side of a request, sent along with the message that asks for
+/// something. Hosts treat it as opaque, and answer it exactly once through a
+/// [ResponseNotify](WindowMessage::ResponseNotify), unless they reject the
+/// message carrying it.
 #[repr(C)]
 pub struct ResponseBinding {
-    out: *mut std::ffi::c_void,
-    wait_flag: *mut std::ffi::c_void, //(std::sync::Mutex<bool>, std::sync::Condvar),
+    complete: ResponseCompleteFn,
 }
 
+type ResponseCompleteFn =
+    unsafe extern "C" fn(*const ResponseBinding, status: i32, data: *const std::ffi::c_void);
+
+/// The status of a successful response.
+pub const RESPONSE_OK: i32 = 0;
+/// The status hosts answer with when they failed to do what was requested.
+/// Other negative values may be used for more specific errors.
+pub const RESPONSE_FAILED: i32 = -1;
+
 #[repr(C, u32)]
 pub enum AppMessage {
     SetWindowSize {
@@ -46,25 +145,168 @@ pub enum AppMessage {
     SetBorderless(bool),
     SetWindowTitle(SafeCString),
     SetWindowResizable(bool),
+    /// Sets the text on the system clipboard. The host copies the string.
+    SetClipboardText(SafeCString),
+    SetCursorVisible(bool),
+    /// In relative mouse mode, the cursor is hidden and captured by the
+    /// window, and [MouseMotion](WindowEvent::MouseMotion) events keep
+    /// reporting relative motion even when the cursor would hit the screen
+    /// borders.
+    SetRelativeMouseMode(bool),
+    SetSystemCursor(SystemCursor),
+    /// Moves the cursor to the given position, relative to the window the
+    /// message is sent to. The host reports the motion as a
+    /// [MouseMotion](WindowEvent::MouseMotion) event.
+    WarpMouse {
+        x: i32,
+        y: i32,
+    },
+    /// Rumbles the controller `id` for `duration_ms` milliseconds. `low` and
+    /// `high` are the intensities of the low and high frequency motors.
+    /// Sending it again replaces the previous rumble, zero intensities stop
+    /// it.
+    RumbleController {
+        id: i32,
+        low: u16,
+        high: u16,
+        duration_ms: u32,
+    },
+    /// Sets the icon of the window. `pixels` points to `width * height` RGBA
+    /// pixels, 8 bits per channel, row by row from the top. The host copies
+    /// them, and rejects icons with a side of zero or above
+    /// [MAX_WINDOW_ICON_SIDE]. See
+    /// [WindowMessenger::set_window_icon](crate::WindowMessenger::set_window_icon).
+    SetWindowIcon {
+        width: u32,
+        height: u32,
+        pixels: *const u8,
+    },
 
-    /// `out_binding` is a vulkan surface handle binding
+    /// Answered with a Vulkan surface handle (`u64`).
     CreateVulkanSurface {
         instance: u64,
-        out_binding: *const ResponseBinding,
+        binding: *const ResponseBinding,
     } = 1025,
-    /// `out_binding` is a `Extent2D` binding
+    /// Answered with the window size in pixels, as an [Extent2D].
     QueryViewportExtents {
-        out_binding: *const ResponseBinding,
+        binding: *const ResponseBinding,
     },
+    /// Gives the host the handle to send window events with. The host never
+    /// dereferences it, only hands it back with every
+    /// [WindowEvent](WindowMessage::WindowEvent). It stays valid for as long
+    /// as the app library is loaded.
     SetMsgBackchannel {
         channel: *const std::ffi::c_void,
     },
+    /// Creates a new window, sharing the host with the other ones. Answered
+    /// with its [WindowInstance].
+    CreateWindow {
+        width: u32,
+        height: u32,
+        binding: *const ResponseBinding,
+    },
+    /// Destroys the window the message is sent to. Anything created from the
+    /// window, like a Vulkan surface, must be destroyed beforehand. The main
+    /// window lives as long as the host does, and cannot be destroyed.
+    DestroyWindow,
+    /// Answered with the text on the system clipboard, as a null terminated
+    /// UTF-8 string. The text is empty if there is nothing on the clipboard.
+    QueryClipboardText {
+        binding: *const ResponseBinding,
+    },
+    /// Answered with the scale factor of the window (an `f32`), which is how
+    /// many pixels there are to each unit of the logical window size.
+    QueryDisplayScale {
+        binding: *const ResponseBinding,
+    },
+    /// Answered with a [RawDisplayList], whose pointers only need to be valid
+    /// until the app returns.
+    QueryDisplays {
+        binding: *const ResponseBinding,
+    },
 
     PumpEvents = 2049,
+    /// The app panicked, and will not send anything else. `message` says why.
+    /// The host should shut down. The host copies the string.
+    AppPanicked {
+        message: SafeCString,
+    },
+    /// A message for a host plugin, which Faisca knows nothing about.
+    /// `payload` points to `len` bytes, and only needs to be valid until the
+    /// host returns, as the host copies it. See
+    /// [CustomMessage](crate::custom::CustomMessage).
+    Custom {
+        kind: u32,
+        payload: *const u8,
+        len: usize,
+    },
 }
 
+/// The version of the message layouts in this file. It must be bumped
+/// whenever any of them changes, and must match `ABI_VERSION` in the host's
+/// `defines.hpp`.
+pub const ABI_VERSION: u32 = 12;
+
+/// The longest side of a [SetWindowIcon](AppMessage::SetWindowIcon) icon, in
+/// pixels. Must match `MAX_WINDOW_ICON_SIDE` in the host's `defines.hpp`.
+pub const MAX_WINDOW_ICON_SIDE: u32 = 4096;
+
+/// The length in bytes of the pixels of a `width` by `height` window icon, or
+/// `None` if hosts reject icons of that size.
+pub(crate) fn window_icon_len(width: u32, height: u32) -> Option<usize> {
+    if !(1..=MAX_WINDOW_ICON_SIDE).contains(&width) || !(1..=MAX_WINDOW_ICON_SIDE).contains(&height)
+    {
+        return None;
+    }
+    (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(4)
+}
+
+/// The host can create and destroy windows other than the main one.
+pub const CAPABILITY_MULTI_WINDOW: u64 = 1 << 0;
+/// The host can read and write the system clipboard.
+pub const CAPABILITY_CLIPBOARD: u64 = 1 << 1;
+/// The host can hide, capture, change and warp the cursor.
+pub const CAPABILITY_CURSOR: u64 = 1 << 2;
+/// The host reports game controllers, and can rumble them.
+pub const CAPABILITY_GAMEPAD: u64 = 1 << 3;
+/// The host may reload the app library while it runs. See
+/// [ReloadRequested](WindowEvent::ReloadRequested).
+pub const CAPABILITY_HOT_RELOAD: u64 = 1 << 4;
+
+/// [faisca_message_app](crate::faisca_message_app) handled the message.
+pub const MESSAGE_ACCEPTED: u32 = 0;
+/// The message was not handled, as the host never said
+/// [Hello](WindowMessage::Hello).
+pub const MESSAGE_REJECTED_NO_HANDSHAKE: u32 = 1;
+/// The message was not handled, as the host was built against a different
+/// [ABI_VERSION].
+pub const MESSAGE_REJECTED_ABI_MISMATCH: u32 = 2;
+/// The app panicked while handling the message. The host also gets an
+/// [AppPanicked](AppMessage::AppPanicked).
+pub const MESSAGE_PANICKED: u32 = 3;
+/// The app has no handler for the kind of the
+/// [Custom](WindowMessage::Custom) message, or its payload is not valid.
+pub const MESSAGE_UNHANDLED: u32 = 4;
+
+/// `faisca_save_state` and `faisca_load_state` succeeded.
+pub const STATE_OK: u32 = 0;
+/// `faisca_save_state` or `faisca_load_state` panicked, so the state is lost.
+pub const STATE_FAILED: u32 = 1;
+
 #[repr(C, u32)]
 pub enum WindowMessage {
+    /// The first message a host sends, before any other. The app rejects every
+    /// message until it gets one with an `abi_version` matching its own
+    /// [ABI_VERSION]. `capabilities` is a combination of the `CAPABILITY_*`
+    /// flags.
+    ///
+    /// The layout of this message must never change.
+    Hello {
+        abi_version: u32,
+        capabilities: u64,
+    } = 0,
     /// This message is only sent once at the beggining of the process execution.
     /// It contains the required instance extensions to be used by the Vulkan driver
     /// initialization.
@@ -74,22 +316,206 @@ pub enum WindowMessage {
         /// The number of C strings on the `names` list.
         count: usize,
     } = 1,
+    /// Answers the request `binding_address` came with. `data` points to the
+    /// answer, and only needs to be valid until the app returns, as the app
+    /// copies it. It may be null if `status` is not [RESPONSE_OK].
     ResponseNotify {
         binding_address: *const ResponseBinding,
+        status: i32,
+        data: *const std::ffi::c_void,
     },
     WindowEvent {
         channel: *mut std::ffi::c_void,
         event: *const WindowEvent,
     },
+    /// A message from a host plugin, the other way around of
+    /// [AppMessage::Custom]. `payload` only needs to be valid until the app
+    /// returns.
+    Custom {
+        kind: u32,
+        payload: *const u8,
+        len: usize,
+    },
 }
 
-#[derive(Clone, Copy)]
+// Not `Copy`, as some events carry strings. The app clones every event it
+// receives, so it owns them, and the host keeps ownership of the ones it sent.
+#[derive(Clone, Debug, PartialEq)]
 #[repr(C, u32)]
 pub enum WindowEvent {
     Quit = 1,
-    WindowResize { w: u32, h: u32 },
+    /// The window was resized. (`w`, `h`) is its logical size, the one to lay
+    /// the UI out with, while (`pixel_w`, `pixel_h`) is its size in pixels,
+    /// the one to size the swapchain with. They only differ on HiDPI screens.
+    WindowResize {
+        w: u32,
+        h: u32,
+        pixel_w: u32,
+        pixel_h: u32,
+    },
+    FocusGained,
+    FocusLost,
+    Minimized,
+    Restored,
+    /// The window was moved, (`x`, `y`) is its new position on the screen.
+    Moved {
+        x: i32,
+        y: i32,
+    },
+    /// The window was exposed and should be redrawn.
+    Exposed,
+    /// The user asked to close the window. When the last window is closed, a
+    /// [Quit](WindowEvent::Quit) follows.
+    CloseRequested,
+    /// The window surface is gone, as happens on Android when the app goes to
+    /// the background. Anything created from it, like a Vulkan surface, must
+    /// be destroyed. See [Renderer::surface_lost](crate::renderer::Renderer::surface_lost).
+    SurfaceLost,
+    /// The window has a surface again, so a new Vulkan surface may be
+    /// created. See
+    /// [Renderer::surface_recreated](crate::renderer::Renderer::surface_recreated).
+    SurfaceRecreated,
+    /// The app is about to go to the background. It may be killed while
+    /// there, so this is the time to save its state.
+    AppWillEnterBackground,
+    AppDidEnterForeground,
+    /// The scale factor of the window changed, as happens when it is moved to
+    /// a screen with a different DPI. A [WindowResize](WindowEvent::WindowResize)
+    /// with the new pixel size follows.
+    ScaleFactorChanged {
+        scale: f32,
+    },
+    /// Files are about to be dropped onto the window, each reported with a
+    /// [DropFile](WindowEvent::DropFile), and then a
+    /// [DropComplete](WindowEvent::DropComplete).
+    DropBegin,
+    /// A file was dropped onto the window. `path` is a UTF-8 path.
+    DropFile {
+        path: SafeCString,
+    },
+    DropComplete,
+    /// The host is about to reload the app library. The app should drop
+    /// everything it created from the host, like its renderer, and return
+    /// from its entry function. The host then saves the app state with
+    /// `faisca_save_state`, loads the library again, hands the state back
+    /// with `faisca_load_state`, and runs the app again on the same windows.
+    /// See [app_state](crate::app_state).
+    ReloadRequested,
+
+    /// A key was pressed. `scancode` is the physical key (an SDL scancode,
+    /// which is the USB HID usage ID), while `keycode` is the key in the
+    /// current keyboard layout (an SDL keycode). `modifiers` is a combination
+    /// of the `KEYMOD_*` flags.
+    KeyDown {
+        scancode: u32,
+        keycode: i32,
+        modifiers: u16,
+        /// Whether this is a key repeat, caused by holding the key down.
+        repeat: bool,
+    } = 1025,
+    /// A key was released. The fields have the same meaning as in
+    /// [KeyDown](WindowEvent::KeyDown).
+    KeyUp {
+        scancode: u32,
+        keycode: i32,
+        modifiers: u16,
+        repeat: bool,
+    },
+    /// The mouse moved to (`x`, `y`), relative to the window. `xrel` and
+    /// `yrel` are the motion since the last `MouseMotion` event.
+    MouseMotion {
+        x: i32,
+        y: i32,
+        xrel: i32,
+        yrel: i32,
+    },
+    /// A mouse button was pressed or released. `button` is one of the
+    /// `MOUSE_BUTTON_*` values.
+    MouseButton {
+        button: u8,
+        pressed: bool,
+        /// 1 for a single click, 2 for a double click, and so on.
+        clicks: u8,
+        x: i32,
+        y: i32,
+    },
+    /// The mouse wheel was scrolled. Positive `y` is away from the user,
+    /// positive `x` is to the right.
+    MouseWheel {
+        x: f32,
+        y: f32,
+    },
+
+    /// A game controller was connected. `id` identifies it until it gets
+    /// removed, and is what the other controller events and messages use.
+    ControllerAdded {
+        id: i32,
+    },
+    ControllerRemoved {
+        id: i32,
+    },
+    /// `button` is one of the `CONTROLLER_BUTTON_*` values.
+    ControllerButton {
+        id: i32,
+        button: u8,
+        pressed: bool,
+    },
+    /// `axis` is one of the `CONTROLLER_AXIS_*` values. Sticks range from
+    /// `-32768` to `32767`, triggers from `0` to `32767`.
+    ControllerAxis {
+        id: i32,
+        axis: u8,
+        value: i16,
+    },
 }
 
+pub const MOUSE_BUTTON_LEFT: u8 = 1;
+pub const MOUSE_BUTTON_MIDDLE: u8 = 2;
+pub const MOUSE_BUTTON_RIGHT: u8 = 3;
+pub const MOUSE_BUTTON_X1: u8 = 4;
+pub const MOUSE_BUTTON_X2: u8 = 5;
+
+pub const CONTROLLER_BUTTON_A: u8 = 0;
+pub const CONTROLLER_BUTTON_B: u8 = 1;
+pub const CONTROLLER_BUTTON_X: u8 = 2;
+pub const CONTROLLER_BUTTON_Y: u8 = 3;
+pub const CONTROLLER_BUTTON_BACK: u8 = 4;
+pub const CONTROLLER_BUTTON_GUIDE: u8 = 5;
+pub const CONTROLLER_BUTTON_START: u8 = 6;
+pub const CONTROLLER_BUTTON_LEFT_STICK: u8 = 7;
+pub const CONTROLLER_BUTTON_RIGHT_STICK: u8 = 8;
+pub const CONTROLLER_BUTTON_LEFT_SHOULDER: u8 = 9;
+pub const CONTROLLER_BUTTON_RIGHT_SHOULDER: u8 = 10;
+pub const CONTROLLER_BUTTON_DPAD_UP: u8 = 11;
+pub const CONTROLLER_BUTTON_DPAD_DOWN: u8 = 12;
+pub const CONTROLLER_BUTTON_DPAD_LEFT: u8 = 13;
+pub const CONTROLLER_BUTTON_DPAD_RIGHT: u8 = 14;
+
+pub const CONTROLLER_AXIS_LEFT_X: u8 = 0;
+pub const CONTROLLER_AXIS_LEFT_Y: u8 = 1;
+pub const CONTROLLER_AXIS_RIGHT_X: u8 = 2;
+pub const CONTROLLER_AXIS_RIGHT_Y: u8 = 3;
+pub const CONTROLLER_AXIS_TRIGGER_LEFT: u8 = 4;
+pub const CONTROLLER_AXIS_TRIGGER_RIGHT: u8 = 5;
+pub const CONTROLLER_AXIS_COUNT: usize = 6;
+
+pub const KEYMOD_NONE: u16 = 0x0000;
+pub const KEYMOD_LSHIFT: u16 = 0x0001;
+pub const KEYMOD_RSHIFT: u16 = 0x0002;
+pub const KEYMOD_LCTRL: u16 = 0x0040;
+pub const KEYMOD_RCTRL: u16 = 0x0080;
+pub const KEYMOD_LALT: u16 = 0x0100;
+pub const KEYMOD_RALT: u16 = 0x0200;
+pub const KEYMOD_LGUI: u16 = 0x0400;
+pub const KEYMOD_RGUI: u16 = 0x0800;
+pub const KEYMOD_NUM: u16 = 0x1000;
+pub const KEYMOD_CAPS: u16 = 0x2000;
+pub const KEYMOD_SHIFT: u16 = KEYMOD_LSHIFT | KEYMOD_RSHIFT;
+pub const KEYMOD_CTRL: u16 = KEYMOD_LCTRL | KEYMOD_RCTRL;
+pub const KEYMOD_ALT: u16 = KEYMOD_LALT | KEYMOD_RALT;
+pub const KEYMOD_GUI: u16 = KEYMOD_LGUI | KEYMOD_RGUI;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct Extent2D {
     pub width: u32,
@@ -97,47 +523,26 @@ pub struct Extent2D {
 }
 
 impl ResponseBinding {
-    pub unsafe fn new(out: *mut std::ffi::c_void) -> Self {
-        let wait_flag = Box::new((std::sync::Mutex::new(false), std::sync::Condvar::new()));
-        Self {
-            wait_flag: Box::into_raw(wait_flag) as *mut std::ffi::c_void,
-            out,
-        }
-    }
-
-    pub fn wait_flag(&self) -> &(std::sync::Mutex<bool>, std::sync::Condvar) {
-        unsafe { &*(self.wait_flag as *mut (std::sync::Mutex<bool>, std::sync::Condvar)) }
-    }
-
-    pub fn reset(&self) {
-        let (ready_mutex, _) = self.wait_flag();
-        let mut ready_guard = ready_mutex.lock().unwrap();
-        *ready_guard = false;
-    }
-
-    pub fn wait(&self) {
-        let (ready_mutex, ready_condvar) = self.wait_flag();
-        let mut ready_guard = ready_mutex.lock().unwrap();
-        while !*ready_guard {
-            ready_guard = ready_condvar.wait(ready_guard).unwrap();
-        }
+    #[inline]
+    pub(crate) fn new(complete: ResponseCompleteFn) -> Self {
+        Self { complete }
     }
 
-    pub fn notify(&self) {
-        let (ready_mutex, ready_condvar) = self.wait_flag();
-        let mut ready_guard = ready_mutex.lock().unwrap();
-        *ready_guard = true;
-        ready_condvar.notify_all();
-    }
-}
-impl Drop for ResponseBinding {
-    fn drop(&mut self) {
-        let condvar = unsafe { Box::from_raw(self.wait_flag) };
-        drop(condvar);
+    /// Hands the host's answer over to whoever is waiting for it.
+    ///
+    /// # Safety
+    /// `binding` must be a binding the host has not answered yet. It may be
+    /// freed by this call.
+    pub(crate) unsafe fn complete(
+        binding: *const ResponseBinding,
+        status: i32,
+        data: *const std::ffi::c_void,
+    ) {
+        ((*binding).complete)(binding, status, data)
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(transparent)]
 pub struct WindowInstance(usize);
 
@@ -146,9 +551,25 @@ impl WindowInstance {
     pub unsafe fn null() -> Self {
         Self(0)
     }
+
+    #[inline(always)]
+    pub(crate) fn from_raw(raw: usize) -> Self {
+        Self(raw)
+    }
+
+    #[inline(always)]
+    pub(crate) fn as_raw(self) -> usize {
+        self.0
+    }
 }
 
 pub type MessageWindowFn = unsafe extern "C" fn(WindowInstance, *const AppMessage) -> u32;
+pub type MessageAppFn = unsafe extern "C" fn(WindowInstance, *const WindowMessage) -> u32;
+pub type RunAppFn = unsafe extern "C" fn(WindowInstance, MessageWindowFn);
+/// Given by the host to `faisca_save_state`, which calls it once with the
+/// saved state. The host copies the data, handing `user` back unchanged.
+pub type StateSinkFn =
+    unsafe extern "C" fn(user: *mut std::ffi::c_void, data: *const u8, len: usize);
 
 /// A function that creates a Vulkan surface (KHR) from a [WindowInstance].
 /// If it succeeds, the [SurfaceKHR](ash::vk::SurfaceKHR) will be written to
@@ -0,0 +1,991 @@
+use crate::{
+    ffi::{
+        window_icon_len, DisplayBounds, DisplayMode, Extent2D, RawDisplay, RawDisplayList,
+        ResponseBinding, ABI_VERSION, CAPABILITY_CLIPBOARD, CAPABILITY_CURSOR, CAPABILITY_GAMEPAD,
+        CAPABILITY_MULTI_WINDOW, MESSAGE_ACCEPTED, MESSAGE_UNHANDLED, RESPONSE_FAILED, RESPONSE_OK,
+    },
+    request::{self, DisplayInfo},
+    AppMessage, CustomMessage, Fullscreen, MessageAppFn, MessageWindowFn, SafeCString,
+    SystemCursor, WindowEvent, WindowInstance, WindowMessage,
+};
+use ash::{
+    extensions::ext,
+    vk::{self, Handle},
+};
+use std::{collections::HashMap, ffi::CString, sync::Mutex};
+
+const VK_KHR_SURFACE_EXTENSION_NAME: &[u8] = b"VK_KHR_surface\0";
+const VK_EXT_HEADLESS_SURFACE_EXTENSION_NAME: &[u8] = b"VK_EXT_headless_surface\0";
+
+const DEFAULT_WINDOW_EXTENT: Extent2D = Extent2D {
+    width: 800,
+    height: 450,
+};
+
+const HOST_CAPABILITIES: u64 =
+    CAPABILITY_MULTI_WINDOW | CAPABILITY_CLIPBOARD | CAPABILITY_CURSOR | CAPABILITY_GAMEPAD;
+
+/// The raw value of the first window the host creates. Zero is reserved for
+/// the null window.
+const MAIN_WINDOW_RAW: usize = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum HeadlessError {
+    #[error("A headless host is already running in this process")]
+    AlreadyRunning,
+    #[error("The app rejected the ABI handshake, code: {0}")]
+    HandshakeRejected(u32),
+}
+
+/// The state of a window living in the headless host. There is no actual
+/// window, this is only what the app asked for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadlessWindow {
+    pub extent: Extent2D,
+    pub title: String,
+    pub fullscreen: Fullscreen,
+    pub borderless: bool,
+    pub resizable: bool,
+    /// Whether the window has a surface. While it doesn't, creating a Vulkan
+    /// surface for it fails.
+    pub has_surface: bool,
+    /// How many pixels there are to each unit of `extent`, which is the
+    /// logical size.
+    pub scale: f32,
+    pub icon: Option<HeadlessIcon>,
+}
+
+impl HeadlessWindow {
+    fn new(extent: Extent2D) -> Self {
+        Self {
+            extent,
+            title: String::from("Faisca Window"),
+            fullscreen: Fullscreen::No,
+            borderless: false,
+            resizable: false,
+            has_surface: true,
+            scale: 1.0,
+            icon: None,
+        }
+    }
+
+    /// The size of the window in pixels.
+    pub fn pixel_extent(&self) -> Extent2D {
+        Extent2D {
+            width: (self.extent.width as f32 * self.scale).round() as u32,
+            height: (self.extent.height as f32 * self.scale).round() as u32,
+        }
+    }
+
+    fn resize_event(&self) -> WindowEvent {
+        let pixel_extent = self.pixel_extent();
+        WindowEvent::WindowResize {
+            w: self.extent.width,
+            h: self.extent.height,
+            pixel_w: pixel_extent.width,
+            pixel_h: pixel_extent.height,
+        }
+    }
+}
+
+/// An icon the app gave a window. See
+/// [SetWindowIcon](AppMessage::SetWindowIcon).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessIcon {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA, 8 bits per channel.
+    pub pixels: Vec<u8>,
+}
+
+/// The cursor state the app asked for. It is shared by every window.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeadlessCursor {
+    pub hidden: bool,
+    pub relative_mode: bool,
+    pub system_cursor: SystemCursor,
+    /// The last position the cursor was warped to, relative to its window.
+    pub position: (i32, i32),
+}
+
+/// A rumble the app asked for. See
+/// [RumbleController](AppMessage::RumbleController).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeadlessRumble {
+    pub id: i32,
+    pub low: u16,
+    pub high: u16,
+    pub duration_ms: u32,
+}
+
+/// A custom message the app sent. See [AppMessage::Custom].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessCustomMessage {
+    pub window: WindowInstance,
+    pub kind: u32,
+    pub payload: Vec<u8>,
+}
+
+struct HostState {
+    message_app: MessageAppFn,
+    /// The back-channel handle given by the app. The host never dereferences
+    /// it.
+    backchannel: usize,
+    windows: HashMap<usize, HeadlessWindow>,
+    /// The raw value of the next window to be created.
+    next_window: usize,
+    /// Stands in for the system clipboard.
+    clipboard: CString,
+    cursor: HeadlessCursor,
+    last_rumble: Option<HeadlessRumble>,
+    displays: Vec<DisplayInfo>,
+    /// The message of the app's [AppPanicked](AppMessage::AppPanicked).
+    app_panic: Option<String>,
+    /// Custom messages not taken yet, oldest first.
+    custom_messages: Vec<HeadlessCustomMessage>,
+    entry: Option<ash::Entry>,
+}
+
+// The host state is global because a `MessageWindowFn` carries no user data.
+// Messages not tied to a window (like `SetMsgBackchannel`) come with the null
+// window, so there is no way of telling hosts apart. That is why only one
+// headless host may exist per process.
+static HOST_STATE: Mutex<Option<HostState>> = Mutex::new(None);
+
+/// A window host that runs in-process and has no actual window. It handles
+/// every [AppMessage] by itself, and creates Vulkan surfaces through
+/// `VK_EXT_headless_surface`, so an app can run without the C++ SDL host (in
+/// CI, for instance).
+///
+/// Messages are handled synchronously, on the thread that sends them. Events
+/// are delivered to the app through the given [MessageAppFn], the same way the
+/// C++ host delivers them.
+pub struct HeadlessHost {
+    main_window: WindowInstance,
+}
+
+impl HeadlessHost {
+    /// Creates the host along with its main window, and tells the app which
+    /// Vulkan instance extensions it needs. `message_app` is usually
+    /// [faisca_message_app](crate::faisca_message_app), or the same function
+    /// loaded from an app library.
+    pub fn new(message_app: MessageAppFn) -> Result<HeadlessHost, HeadlessError> {
+        {
+            let mut state_guard = HOST_STATE.lock().unwrap();
+            if state_guard.is_some() {
+                return Err(HeadlessError::AlreadyRunning);
+            }
+
+            *state_guard = Some(HostState {
+                message_app,
+                backchannel: 0,
+                windows: HashMap::from([(
+                    MAIN_WINDOW_RAW,
+                    HeadlessWindow::new(DEFAULT_WINDOW_EXTENT),
+                )]),
+                next_window: MAIN_WINDOW_RAW + 1,
+                clipboard: CString::default(),
+                cursor: HeadlessCursor::default(),
+                last_rumble: None,
+                displays: vec![default_display()],
+                app_panic: None,
+                custom_messages: Vec::new(),
+                entry: None,
+            });
+        }
+
+        let main_window = WindowInstance::from_raw(MAIN_WINDOW_RAW);
+
+        let hello_result = unsafe {
+            message_app(
+                main_window,
+                &WindowMessage::Hello {
+                    abi_version: ABI_VERSION,
+                    capabilities: HOST_CAPABILITIES,
+                },
+            )
+        };
+        if hello_result != MESSAGE_ACCEPTED {
+            *HOST_STATE.lock().unwrap() = None;
+            return Err(HeadlessError::HandshakeRejected(hello_result));
+        }
+
+        // The app keeps the pointers, not the strings, so they must be static
+        let required_extensions = [
+            VK_KHR_SURFACE_EXTENSION_NAME.as_ptr() as *const i8,
+            VK_EXT_HEADLESS_SURFACE_EXTENSION_NAME.as_ptr() as *const i8,
+        ];
+        unsafe {
+            message_app(
+                main_window,
+                &WindowMessage::VulkanRequiredInstanceExtensions {
+                    names: required_extensions.as_ptr(),
+                    count: required_extensions.len(),
+                },
+            )
+        };
+
+        Ok(HeadlessHost { main_window })
+    }
+
+    #[inline]
+    pub fn window(&self) -> WindowInstance {
+        self.main_window
+    }
+
+    /// The function to be handed to the app, in place of the C++ host's
+    /// `FaiscaMessageWindow`.
+    #[inline]
+    pub fn message_window_fn(&self) -> MessageWindowFn {
+        headless_message_window
+    }
+
+    /// Returns a snapshot of the window state, or `None` if there is no such
+    /// window.
+    pub fn window_state(&self, w: WindowInstance) -> Option<HeadlessWindow> {
+        with_host_state(|state| state.windows.get(&w.as_raw()).cloned()).flatten()
+    }
+
+    /// Returns a snapshot of the cursor state.
+    pub fn cursor_state(&self) -> HeadlessCursor {
+        with_host_state(|state| state.cursor.clone()).unwrap_or_default()
+    }
+
+    /// Returns the last rumble the app asked for. There are no actual
+    /// controllers, they only exist through the events sent with
+    /// [send_event](HeadlessHost::send_event).
+    pub fn last_rumble(&self) -> Option<HeadlessRumble> {
+        with_host_state(|state| state.last_rumble).flatten()
+    }
+
+    /// Returns why the app panicked, if it did.
+    pub fn app_panic(&self) -> Option<String> {
+        with_host_state(|state| state.app_panic.clone()).flatten()
+    }
+
+    /// Returns the custom messages the app sent since the last call, as
+    /// there are no plugins to handle them.
+    pub fn take_custom_messages(&self) -> Vec<HeadlessCustomMessage> {
+        with_host_state(|state| std::mem::take(&mut state.custom_messages)).unwrap_or_default()
+    }
+
+    /// Sends a custom message to the app, as a host plugin would. Returns
+    /// what the app answered with, one of the `MESSAGE_*` values.
+    pub fn send_custom<M: CustomMessage>(&self, w: WindowInstance, msg: &M) -> u32 {
+        let Some(message_app) = with_host_state(|state| state.message_app) else {
+            return MESSAGE_UNHANDLED;
+        };
+
+        let payload = msg.encode();
+        unsafe {
+            message_app(
+                w,
+                &WindowMessage::Custom {
+                    kind: M::KIND,
+                    payload: payload.as_ptr(),
+                    len: payload.len(),
+                },
+            )
+        }
+    }
+
+    /// Replaces the displays the host reports. There is a single 1080p one
+    /// by default.
+    pub fn set_displays(&self, displays: Vec<DisplayInfo>) {
+        with_host_state(|state| state.displays = displays);
+    }
+
+    /// Sends an event to the app, as if it came from the window system.
+    pub fn send_event(&self, w: WindowInstance, event: WindowEvent) {
+        deliver_event(w, event);
+    }
+
+    /// Simulates the user resizing the window. The size is the logical one.
+    pub fn resize(&self, w: WindowInstance, width: u32, height: u32) {
+        if let Some(event) = resize_window(w, Extent2D { width, height }) {
+            deliver_event(w, event);
+        }
+    }
+
+    /// Simulates the window being moved to a screen with a different scale
+    /// factor. The logical size is kept, so the pixel size changes.
+    pub fn set_scale_factor(&self, w: WindowInstance, scale: f32) {
+        let resize_event = with_host_state(|state| {
+            state.windows.get_mut(&w.as_raw()).map(|window| {
+                window.scale = scale;
+                window.resize_event()
+            })
+        })
+        .flatten();
+
+        if let Some(resize_event) = resize_event {
+            deliver_event(w, WindowEvent::ScaleFactorChanged { scale });
+            deliver_event(w, resize_event);
+        }
+    }
+
+    /// Simulates the user dropping files onto the window.
+    pub fn drop_files<P: AsRef<str>>(&self, w: WindowInstance, paths: &[P]) {
+        deliver_event(w, WindowEvent::DropBegin);
+        for path in paths {
+            match SafeCString::allocate_from_str(path) {
+                Ok(path) => deliver_event(w, WindowEvent::DropFile { path }),
+                Err(e) => log::warn!("Not dropping file with invalid path: {e}"),
+            }
+        }
+        deliver_event(w, WindowEvent::DropComplete);
+    }
+
+    /// Simulates the user clicking the close button of a window.
+    pub fn request_close(&self, w: WindowInstance) {
+        deliver_event(w, WindowEvent::CloseRequested);
+    }
+
+    /// Simulates the app being sent to the background, the way Android does
+    /// it: every window loses its surface.
+    pub fn enter_background(&self) {
+        deliver_event(self.main_window, WindowEvent::AppWillEnterBackground);
+        for w in self.set_surfaces_available(false) {
+            deliver_event(w, WindowEvent::SurfaceLost);
+        }
+    }
+
+    /// Simulates the app coming back from the background, giving every
+    /// window a surface again.
+    pub fn enter_foreground(&self) {
+        for w in self.set_surfaces_available(true) {
+            deliver_event(w, WindowEvent::SurfaceRecreated);
+        }
+        deliver_event(self.main_window, WindowEvent::AppDidEnterForeground);
+    }
+
+    /// Returns the windows whose surface availability changed.
+    fn set_surfaces_available(&self, available: bool) -> Vec<WindowInstance> {
+        let mut changed: Vec<_> = with_host_state(|state| {
+            state
+                .windows
+                .iter_mut()
+                .filter(|(_, window)| window.has_surface != available)
+                .map(|(&raw, window)| {
+                    window.has_surface = available;
+                    raw
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+        changed.sort_unstable();
+        changed.into_iter().map(WindowInstance::from_raw).collect()
+    }
+
+    /// Simulates the user closing the main window.
+    pub fn quit(&self) {
+        deliver_event(self.main_window, WindowEvent::Quit);
+    }
+}
+impl Drop for HeadlessHost {
+    fn drop(&mut self) {
+        *HOST_STATE.lock().unwrap() = None;
+    }
+}
+
+fn default_display() -> DisplayInfo {
+    let modes = vec![
+        DisplayMode {
+            width: 1920,
+            height: 1080,
+            refresh_rate: 60,
+        },
+        DisplayMode {
+            width: 1280,
+            height: 720,
+            refresh_rate: 60,
+        },
+    ];
+    DisplayInfo {
+        name: String::from("Headless Display"),
+        bounds: DisplayBounds {
+            x: 0,
+            y: 0,
+            width: 1920,
+            height: 1080,
+        },
+        current_mode: modes[0],
+        modes,
+    }
+}
+
+/// Sets the logical size of the window, returning the event reporting it.
+fn resize_window(w: WindowInstance, extent: Extent2D) -> Option<WindowEvent> {
+    with_host_state(|state| {
+        state.windows.get_mut(&w.as_raw()).map(|window| {
+            window.extent = extent;
+            window.resize_event()
+        })
+    })
+    .flatten()
+}
+
+fn with_host_state<R>(f: impl FnOnce(&mut HostState) -> R) -> Option<R> {
+    HOST_STATE.lock().unwrap().as_mut().map(f)
+}
+
+fn deliver_event(w: WindowInstance, event: WindowEvent) {
+    let Some((message_app, backchannel)) =
+        with_host_state(|state| (state.message_app, state.backchannel))
+    else {
+        log::warn!("Dropping window event: headless host is not running");
+        return;
+    };
+
+    // The state lock must not be held here, as the app may answer by sending
+    // messages back to the host.
+    unsafe {
+        message_app(
+            w,
+            &WindowMessage::WindowEvent {
+                channel: backchannel as *mut std::ffi::c_void,
+                event: &event as *const WindowEvent,
+            },
+        )
+    };
+}
+
+/// Answers a request, with a failure status if there is no `answer`.
+fn notify_response<T>(w: WindowInstance, binding: *const ResponseBinding, answer: Option<&T>) {
+    let Some(message_app) = with_host_state(|state| state.message_app) else {
+        return;
+    };
+
+    let (status, data) = match answer {
+        Some(answer) => (RESPONSE_OK, answer as *const T as *const std::ffi::c_void),
+        None => (RESPONSE_FAILED, std::ptr::null()),
+    };
+    unsafe {
+        message_app(
+            w,
+            &WindowMessage::ResponseNotify {
+                binding_address: binding,
+                status,
+                data,
+            },
+        )
+    };
+}
+
+fn create_headless_surface(state: &mut HostState, instance: u64) -> Option<vk::SurfaceKHR> {
+    let entry = match &mut state.entry {
+        Some(entry) => entry,
+        entry @ None => match unsafe { ash::Entry::load() } {
+            Ok(loaded) => entry.insert(loaded),
+            Err(e) => {
+                log::error!("Headless host failed to load Vulkan: {e}");
+                return None;
+            }
+        },
+    };
+
+    let instance =
+        unsafe { ash::Instance::load(entry.static_fn(), vk::Instance::from_raw(instance)) };
+    let headless_surface_loader = ext::HeadlessSurface::new(entry, &instance);
+
+    unsafe {
+        headless_surface_loader
+            .create_headless_surface(&vk::HeadlessSurfaceCreateInfoEXT::default(), None)
+    }
+    .map_err(|e| log::error!("Failed to create headless Vulkan surface: {e}"))
+    .ok()
+}
+
+unsafe extern "C" fn headless_message_window(w: WindowInstance, msg: *const AppMessage) -> u32 {
+    let handled = match &*msg {
+        AppMessage::SetWindowSize { width, height } => {
+            let extent = Extent2D {
+                width: *width,
+                height: *height,
+            };
+
+            // Just like the SDL host, we answer with a resize event
+            match resize_window(w, extent) {
+                Some(event) => {
+                    deliver_event(w, event);
+                    true
+                }
+                None => false,
+            }
+        }
+        AppMessage::SetFullscreen(fullscreen) => {
+            let fullscreen = *fullscreen;
+            let mode_available = with_host_state(|state| match fullscreen {
+                Fullscreen::Real { display, mode } => state
+                    .displays
+                    .get(display as usize)
+                    .is_some_and(|display| display.modes.contains(&mode)),
+                _ => true,
+            })
+            .unwrap_or(false);
+
+            mode_available && with_window(w, |window| window.fullscreen = fullscreen)
+        }
+        AppMessage::SetBorderless(borderless) => {
+            let borderless = *borderless;
+            with_window(w, |window| window.borderless = borderless)
+        }
+        AppMessage::SetWindowTitle(title) => {
+            // We copy the string, as the app frees it once we return
+            let title = title.as_cstr().to_string_lossy().into_owned();
+            with_window(w, |window| window.title = title)
+        }
+        AppMessage::SetWindowIcon {
+            width,
+            height,
+            pixels,
+        } => {
+            // Just like the SDL host, we reject sizes that would read too far
+            match window_icon_len(*width, *height) {
+                Some(len) => {
+                    // We copy the pixels, as the app frees them once we return
+                    let icon = HeadlessIcon {
+                        width: *width,
+                        height: *height,
+                        pixels: request::raw_slice(*pixels, len).to_vec(),
+                    };
+                    with_window(w, |window| window.icon = Some(icon))
+                }
+                None => {
+                    log::error!("Rejecting a {width}x{height} window icon");
+                    false
+                }
+            }
+        }
+        AppMessage::SetWindowResizable(resizable) => {
+            let resizable = *resizable;
+            with_window(w, |window| window.resizable = resizable)
+        }
+        AppMessage::SetClipboardText(text) => {
+            // We copy the string, as the app frees it once we return
+            let text = text.as_cstr().to_owned();
+            with_host_state(|state| state.clipboard = text).is_some()
+        }
+
+        AppMessage::CreateVulkanSurface { instance, binding } => {
+            let instance = *instance;
+            let surface = with_host_state(|state| match state.windows.get(&w.as_raw()) {
+                Some(window) if window.has_surface => create_headless_surface(state, instance),
+                _ => None,
+            });
+            match surface {
+                Some(surface) => {
+                    let surface = surface.map(|surface| surface.as_raw());
+                    notify_response(w, *binding, surface.as_ref());
+                    true
+                }
+                None => false,
+            }
+        }
+        AppMessage::QueryViewportExtents { binding } => {
+            let extent = with_host_state(|state| {
+                state
+                    .windows
+                    .get(&w.as_raw())
+                    .map(|window| window.pixel_extent())
+            })
+            .flatten();
+            if let Some(extent) = extent {
+                notify_response(w, *binding, Some(&extent));
+            }
+            extent.is_some()
+        }
+        AppMessage::SetMsgBackchannel { channel } => {
+            let channel = *channel as usize;
+            with_host_state(|state| state.backchannel = channel).is_some()
+        }
+        AppMessage::CreateWindow {
+            width,
+            height,
+            binding,
+        } => {
+            let extent = Extent2D {
+                width: *width,
+                height: *height,
+            };
+            let created = with_host_state(|state| {
+                let raw = state.next_window;
+                state.next_window += 1;
+                state.windows.insert(raw, HeadlessWindow::new(extent));
+                WindowInstance::from_raw(raw)
+            });
+            match created {
+                Some(window) => {
+                    notify_response(w, *binding, Some(&window));
+                    true
+                }
+                None => false,
+            }
+        }
+        AppMessage::SetCursorVisible(visible) => {
+            let hidden = !*visible;
+            with_host_state(|state| state.cursor.hidden = hidden).is_some()
+        }
+        AppMessage::SetRelativeMouseMode(enabled) => {
+            let enabled = *enabled;
+            with_host_state(|state| state.cursor.relative_mode = enabled).is_some()
+        }
+        AppMessage::SetSystemCursor(system_cursor) => {
+            let system_cursor = *system_cursor;
+            with_host_state(|state| state.cursor.system_cursor = system_cursor).is_some()
+        }
+        AppMessage::RumbleController {
+            id,
+            low,
+            high,
+            duration_ms,
+        } => {
+            let rumble = HeadlessRumble {
+                id: *id,
+                low: *low,
+                high: *high,
+                duration_ms: *duration_ms,
+            };
+            with_host_state(|state| state.last_rumble = Some(rumble)).is_some()
+        }
+        AppMessage::WarpMouse { x, y } => {
+            let (x, y) = (*x, *y);
+            let previous = with_host_state(|state| {
+                let window_exists = state.windows.contains_key(&w.as_raw());
+                window_exists.then(|| std::mem::replace(&mut state.cursor.position, (x, y)))
+            })
+            .flatten();
+
+            // Just like SDL, warping produces a motion event
+            if let Some((prev_x, prev_y)) = previous {
+                deliver_event(
+                    w,
+                    WindowEvent::MouseMotion {
+                        x,
+                        y,
+                        xrel: x - prev_x,
+                        yrel: y - prev_y,
+                    },
+                );
+            }
+            previous.is_some()
+        }
+        AppMessage::QueryClipboardText { binding } => {
+            match with_host_state(|state| state.clipboard.clone()) {
+                Some(text) => {
+                    // The answer is the string itself, not a pointer to it
+                    notify_response(w, *binding, Some(&*text.as_ptr()));
+                    true
+                }
+                None => false,
+            }
+        }
+        AppMessage::QueryDisplayScale { binding } => {
+            let scale =
+                with_host_state(|state| state.windows.get(&w.as_raw()).map(|window| window.scale))
+                    .flatten();
+            if let Some(scale) = scale {
+                notify_response(w, *binding, Some(&scale));
+            }
+            scale.is_some()
+        }
+        AppMessage::QueryDisplays { binding } => {
+            match with_host_state(|state| state.displays.clone()) {
+                Some(displays) => {
+                    let names: Vec<_> = displays
+                        .iter()
+                        .map(|display| CString::new(display.name.as_str()).unwrap_or_default())
+                        .collect();
+                    let raw_displays: Vec<_> = displays
+                        .iter()
+                        .zip(&names)
+                        .map(|(display, name)| RawDisplay {
+                            name: name.as_ptr(),
+                            bounds: display.bounds,
+                            current_mode: display.current_mode,
+                            modes: display.modes.as_ptr(),
+                            mode_count: display.modes.len(),
+                        })
+                        .collect();
+                    let list = RawDisplayList {
+                        displays: raw_displays.as_ptr(),
+                        count: raw_displays.len(),
+                    };
+                    notify_response(w, *binding, Some(&list));
+                    true
+                }
+                None => false,
+            }
+        }
+        AppMessage::DestroyWindow => {
+            // The main window lives as long as the host does
+            w.as_raw() != MAIN_WINDOW_RAW
+                && with_host_state(|state| state.windows.remove(&w.as_raw()).is_some())
+                    .unwrap_or(false)
+        }
+
+        // There is no event loop to pump, events are delivered as they happen
+        AppMessage::PumpEvents => true,
+        AppMessage::AppPanicked { message } => {
+            // We copy the string, as the app frees it once we return
+            let message = message.as_cstr().to_string_lossy().into_owned();
+            log::error!("The app panicked: {message}");
+            with_host_state(|state| state.app_panic = Some(message)).is_some()
+        }
+        AppMessage::Custom { kind, payload, len } => {
+            let message = HeadlessCustomMessage {
+                window: w,
+                kind: *kind,
+                payload: request::raw_slice(*payload, *len).to_vec(),
+            };
+            with_host_state(|state| state.custom_messages.push(message)).is_some()
+        }
+    };
+
+    if handled {
+        1
+    } else {
+        0
+    }
+}
+
+fn with_window(w: WindowInstance, f: impl FnOnce(&mut HeadlessWindow)) -> bool {
+    with_host_state(|state| state.windows.get_mut(&w.as_raw()).map(f).is_some()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{custom, ResponseError, SafeCString, WindowMessenger};
+    use std::sync::mpsc;
+
+    #[derive(Debug, PartialEq)]
+    struct Achievement(u32);
+
+    impl CustomMessage for Achievement {
+        const KIND: u32 = 7;
+
+        fn encode(&self) -> Vec<u8> {
+            self.0.to_le_bytes().to_vec()
+        }
+
+        fn decode(payload: &[u8]) -> Option<Self> {
+            Some(Achievement(u32::from_le_bytes(payload.try_into().ok()?)))
+        }
+    }
+
+    #[test]
+    fn headless_roundtrip_test() {
+        let host = HeadlessHost::new(crate::faisca_message_app).unwrap();
+        let w = host.window();
+        let messenger = unsafe { WindowMessenger::from_raw(host.message_window_fn()) };
+
+        messenger.send(
+            w,
+            &AppMessage::SetWindowSize {
+                width: 640,
+                height: 480,
+            },
+        );
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((
+                _,
+                WindowEvent::WindowResize {
+                    w: 640,
+                    h: 480,
+                    pixel_w: 640,
+                    pixel_h: 480
+                }
+            ))
+        ));
+
+        assert_eq!(
+            messenger.request(w, request::QueryViewportExtents).wait(),
+            Ok(Extent2D {
+                width: 640,
+                height: 480
+            })
+        );
+
+        host.set_scale_factor(w, 2.0);
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::ScaleFactorChanged { scale })) if scale == 2.0
+        ));
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((
+                _,
+                WindowEvent::WindowResize {
+                    w: 640,
+                    h: 480,
+                    pixel_w: 1280,
+                    pixel_h: 960
+                }
+            ))
+        ));
+        assert_eq!(
+            messenger.request(w, request::QueryDisplayScale).wait(),
+            Ok(2.0)
+        );
+        assert_eq!(
+            messenger.request(w, request::QueryViewportExtents).wait(),
+            Ok(Extent2D {
+                width: 1280,
+                height: 960
+            })
+        );
+
+        let displays = messenger.request(w, request::QueryDisplays).wait().unwrap();
+        assert_eq!(displays, vec![default_display()]);
+        let fullscreen = Fullscreen::Real {
+            display: 0,
+            mode: displays[0].modes[1],
+        };
+        messenger.send(w, &AppMessage::SetFullscreen(fullscreen));
+        assert_eq!(host.window_state(w).unwrap().fullscreen, fullscreen);
+        // Not a mode the display has
+        messenger.send(
+            w,
+            &AppMessage::SetFullscreen(Fullscreen::Real {
+                display: 0,
+                mode: DisplayMode::default(),
+            }),
+        );
+        assert_eq!(host.window_state(w).unwrap().fullscreen, fullscreen);
+        messenger.send(w, &AppMessage::SetFullscreen(Fullscreen::No));
+
+        messenger.send(w, &AppMessage::SetWindowResizable(true));
+        assert!(host.window_state(w).unwrap().resizable);
+
+        let pixels = [255, 128, 0, 255].repeat(4);
+        assert!(messenger.set_window_icon(w, 2, 2, &pixels));
+        assert_eq!(
+            host.window_state(w).unwrap().icon,
+            Some(HeadlessIcon {
+                width: 2,
+                height: 2,
+                pixels: pixels.clone(),
+            })
+        );
+        assert!(!messenger.set_window_icon(w, 3, 2, &pixels));
+        // Sizes whose length would wrap, or be zero, never reach the pixels
+        for (width, height) in [(u32::MAX, u32::MAX), (0, 2)] {
+            assert!(!messenger.send(
+                w,
+                &AppMessage::SetWindowIcon {
+                    width,
+                    height,
+                    pixels: pixels.as_ptr(),
+                },
+            ));
+        }
+        assert_eq!(host.window_state(w).unwrap().icon.unwrap().width, 2);
+
+        messenger.send(
+            w,
+            &AppMessage::SetClipboardText(SafeCString::allocate_from_str("copied").unwrap()),
+        );
+        assert_eq!(
+            messenger.request(w, request::QueryClipboardText).wait(),
+            Ok(String::from("copied"))
+        );
+
+        messenger.send(w, &AppMessage::SetRelativeMouseMode(true));
+        messenger.send(w, &AppMessage::WarpMouse { x: 20, y: 10 });
+        assert!(host.cursor_state().relative_mode);
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::MouseMotion { x: 20, y: 10, .. }))
+        ));
+
+        let second = messenger
+            .request(
+                w,
+                request::CreateWindow {
+                    width: 320,
+                    height: 240,
+                },
+            )
+            .wait()
+            .unwrap();
+        assert_ne!(second, w);
+        assert_eq!(
+            host.window_state(second).unwrap().extent,
+            Extent2D {
+                width: 320,
+                height: 240
+            }
+        );
+        messenger.send(second, &AppMessage::DestroyWindow);
+        assert!(host.window_state(second).is_none());
+
+        host.drop_files(w, &["model.gltf"]);
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::DropBegin))
+        ));
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::DropFile { path })) if path.as_cstr().to_str() == Ok("model.gltf")
+        ));
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::DropComplete))
+        ));
+
+        host.enter_background();
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::AppWillEnterBackground))
+        ));
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::SurfaceLost))
+        ));
+        let surface = messenger
+            .request(
+                w,
+                request::CreateVulkanSurface {
+                    instance: vk::Instance::null(),
+                },
+            )
+            .wait();
+        assert_eq!(surface, Err(ResponseError::HostError(RESPONSE_FAILED)));
+
+        host.enter_foreground();
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::SurfaceRecreated))
+        ));
+        assert!(host.window_state(w).unwrap().has_surface);
+
+        assert!(matches!(
+            messenger.try_recv(),
+            Some((_, WindowEvent::AppDidEnterForeground))
+        ));
+
+        assert!(messenger.send_custom(w, &Achievement(3)));
+        assert_eq!(
+            host.take_custom_messages(),
+            vec![HeadlessCustomMessage {
+                window: w,
+                kind: Achievement::KIND,
+                payload: vec![3, 0, 0, 0],
+            }]
+        );
+        assert_eq!(host.send_custom(w, &Achievement(5)), MESSAGE_UNHANDLED);
+        let (sender, receiver) = mpsc::channel();
+        custom::register_handler(move |_, achievement: Achievement| {
+            sender.send(achievement).unwrap()
+        });
+        assert_eq!(host.send_custom(w, &Achievement(5)), MESSAGE_ACCEPTED);
+        assert_eq!(receiver.try_recv(), Ok(Achievement(5)));
+        custom::unregister_handler(Achievement::KIND);
+
+        host.quit();
+        assert!(matches!(messenger.try_recv(), Some((_, WindowEvent::Quit))));
+    }
+}
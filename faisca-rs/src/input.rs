@@ -0,0 +1,351 @@
+use crate::{ffi::CONTROLLER_AXIS_COUNT, WChanMsg, WindowEvent, WindowMessenger};
+use std::collections::{HashMap, HashSet};
+
+/// Keeps track of the keyboard and mouse state, built from the window events.
+///
+/// Events are fed either through [next_event](InputState::next_event), which
+/// wraps [WindowMessenger::try_recv], or by hand through
+/// [process_event](InputState::process_event). Per-frame values, such as the
+/// mouse delta, accumulate until [begin_frame](InputState::begin_frame) is
+/// called.
+#[derive(Clone, Debug, Default)]
+pub struct InputState {
+    held_scancodes: HashSet<u32>,
+    held_keycodes: HashSet<i32>,
+    modifiers: u16,
+    /// Bit `n` is set if mouse button `n` is held.
+    held_mouse_buttons: u32,
+    mouse_position: (i32, i32),
+    mouse_delta: (i32, i32),
+    wheel_delta: (f32, f32),
+    gamepads: GamepadState,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Receives the next window event, if there is one, updating the input
+    /// state before handing it over.
+    pub fn next_event(&mut self, messenger: &WindowMessenger) -> Option<WChanMsg> {
+        let msg = messenger.try_recv()?;
+        self.process_event(&msg.1);
+        Some(msg)
+    }
+
+    /// Resets the per-frame values. Call it once at the start of each frame,
+    /// before receiving the frame's events.
+    pub fn begin_frame(&mut self) {
+        self.mouse_delta = (0, 0);
+        self.wheel_delta = (0.0, 0.0);
+    }
+
+    pub fn process_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::KeyDown {
+                scancode,
+                keycode,
+                modifiers,
+                ..
+            } => {
+                self.held_scancodes.insert(scancode);
+                self.held_keycodes.insert(keycode);
+                self.modifiers = modifiers;
+            }
+            WindowEvent::KeyUp {
+                scancode,
+                keycode,
+                modifiers,
+                ..
+            } => {
+                self.held_scancodes.remove(&scancode);
+                self.held_keycodes.remove(&keycode);
+                self.modifiers = modifiers;
+            }
+            WindowEvent::MouseMotion { x, y, xrel, yrel } => {
+                self.mouse_position = (x, y);
+                self.mouse_delta.0 = self.mouse_delta.0.saturating_add(xrel);
+                self.mouse_delta.1 = self.mouse_delta.1.saturating_add(yrel);
+            }
+            WindowEvent::MouseButton {
+                button,
+                pressed,
+                x,
+                y,
+                ..
+            } => {
+                self.mouse_position = (x, y);
+                if let Some(bit) = 1u32.checked_shl(button.into()) {
+                    if pressed {
+                        self.held_mouse_buttons |= bit;
+                    } else {
+                        self.held_mouse_buttons &= !bit;
+                    }
+                }
+            }
+            WindowEvent::MouseWheel { x, y } => {
+                self.wheel_delta.0 += x;
+                self.wheel_delta.1 += y;
+            }
+            // Whatever is released from now on, another window gets told
+            WindowEvent::FocusLost => {
+                self.held_scancodes.clear();
+                self.held_keycodes.clear();
+                self.modifiers = 0;
+                self.held_mouse_buttons = 0;
+            }
+            _ => self.gamepads.process_event(event),
+        }
+    }
+
+    /// Whether the key with the given scancode (physical key) is held.
+    #[inline]
+    pub fn is_key_held(&self, scancode: u32) -> bool {
+        self.held_scancodes.contains(&scancode)
+    }
+
+    /// Whether the key with the given keycode (key in the current layout) is
+    /// held.
+    #[inline]
+    pub fn is_keycode_held(&self, keycode: i32) -> bool {
+        self.held_keycodes.contains(&keycode)
+    }
+
+    /// The `KEYMOD_*` flags of the last keyboard event.
+    #[inline]
+    pub fn modifiers(&self) -> u16 {
+        self.modifiers
+    }
+
+    /// Whether the given `MOUSE_BUTTON_*` button is held.
+    #[inline]
+    pub fn is_mouse_button_held(&self, button: u8) -> bool {
+        1u32.checked_shl(button.into())
+            .is_some_and(|bit| self.held_mouse_buttons & bit != 0)
+    }
+
+    /// The last known mouse position, relative to the window.
+    #[inline]
+    pub fn mouse_position(&self) -> (i32, i32) {
+        self.mouse_position
+    }
+
+    /// How much the mouse moved since [begin_frame](InputState::begin_frame).
+    #[inline]
+    pub fn mouse_delta(&self) -> (i32, i32) {
+        self.mouse_delta
+    }
+
+    /// How much the mouse wheel scrolled since
+    /// [begin_frame](InputState::begin_frame).
+    #[inline]
+    pub fn wheel_delta(&self) -> (f32, f32) {
+        self.wheel_delta
+    }
+
+    /// The connected game controllers.
+    #[inline]
+    pub fn gamepads(&self) -> &GamepadState {
+        &self.gamepads
+    }
+}
+
+/// The state of a single game controller.
+#[derive(Clone, Debug, Default)]
+pub struct Gamepad {
+    /// Bit `n` is set if button `n` is held.
+    held_buttons: u32,
+    axes: [i16; CONTROLLER_AXIS_COUNT],
+}
+
+impl Gamepad {
+    /// Whether the given `CONTROLLER_BUTTON_*` button is held.
+    #[inline]
+    pub fn is_button_held(&self, button: u8) -> bool {
+        1u32.checked_shl(button.into())
+            .is_some_and(|bit| self.held_buttons & bit != 0)
+    }
+
+    /// The raw value of the given `CONTROLLER_AXIS_*` axis, zero for unknown
+    /// axes.
+    #[inline]
+    pub fn axis(&self, axis: u8) -> i16 {
+        self.axes.get(usize::from(axis)).copied().unwrap_or(0)
+    }
+
+    /// The value of the given `CONTROLLER_AXIS_*` axis, mapped to `-1.0..=1.0`
+    /// (`0.0..=1.0` for triggers).
+    #[inline]
+    pub fn axis_normalized(&self, axis: u8) -> f32 {
+        (f32::from(self.axis(axis)) / f32::from(i16::MAX)).max(-1.0)
+    }
+}
+
+/// Keeps track of the connected game controllers, keyed by controller id,
+/// built from the window events.
+///
+/// [InputState] keeps one already, this is for apps that only care about
+/// controllers.
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    gamepads: HashMap<i32, Gamepad>,
+}
+
+impl GamepadState {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn process_event(&mut self, event: &WindowEvent) {
+        match *event {
+            WindowEvent::ControllerAdded { id } => {
+                self.gamepads.insert(id, Gamepad::default());
+            }
+            WindowEvent::ControllerRemoved { id } => {
+                self.gamepads.remove(&id);
+            }
+            WindowEvent::ControllerButton {
+                id,
+                button,
+                pressed,
+            } => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                if let Some(bit) = 1u32.checked_shl(button.into()) {
+                    if pressed {
+                        gamepad.held_buttons |= bit;
+                    } else {
+                        gamepad.held_buttons &= !bit;
+                    }
+                }
+            }
+            WindowEvent::ControllerAxis { id, axis, value } => {
+                let gamepad = self.gamepads.entry(id).or_default();
+                if let Some(axis_value) = gamepad.axes.get_mut(usize::from(axis)) {
+                    *axis_value = value;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the controller with the given id, if it is connected.
+    #[inline]
+    pub fn gamepad(&self, id: i32) -> Option<&Gamepad> {
+        self.gamepads.get(&id)
+    }
+
+    /// The ids of the connected controllers, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.gamepads.keys().copied()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.gamepads.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.gamepads.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{
+        CONTROLLER_AXIS_LEFT_X, CONTROLLER_BUTTON_A, CONTROLLER_BUTTON_B, KEYMOD_LSHIFT,
+        KEYMOD_NONE, MOUSE_BUTTON_LEFT, MOUSE_BUTTON_RIGHT,
+    };
+
+    #[test]
+    fn input_state_test() {
+        let mut input = InputState::new();
+
+        input.process_event(&WindowEvent::KeyDown {
+            scancode: 4,
+            keycode: 'a' as i32,
+            modifiers: KEYMOD_LSHIFT,
+            repeat: false,
+        });
+        assert!(input.is_key_held(4));
+        assert!(input.is_keycode_held('a' as i32));
+        assert_eq!(input.modifiers(), KEYMOD_LSHIFT);
+
+        input.process_event(&WindowEvent::KeyUp {
+            scancode: 4,
+            keycode: 'a' as i32,
+            modifiers: KEYMOD_NONE,
+            repeat: false,
+        });
+        assert!(!input.is_key_held(4));
+
+        input.process_event(&WindowEvent::MouseMotion {
+            x: 10,
+            y: 10,
+            xrel: 3,
+            yrel: -2,
+        });
+        input.process_event(&WindowEvent::MouseMotion {
+            x: 15,
+            y: 9,
+            xrel: 5,
+            yrel: -1,
+        });
+        input.process_event(&WindowEvent::MouseButton {
+            button: MOUSE_BUTTON_LEFT,
+            pressed: true,
+            clicks: 1,
+            x: 15,
+            y: 9,
+        });
+        assert_eq!(input.mouse_delta(), (8, -3));
+        assert_eq!(input.mouse_position(), (15, 9));
+        assert!(input.is_mouse_button_held(MOUSE_BUTTON_LEFT));
+        assert!(!input.is_mouse_button_held(MOUSE_BUTTON_RIGHT));
+
+        input.begin_frame();
+        assert_eq!(input.mouse_delta(), (0, 0));
+        assert!(input.is_mouse_button_held(MOUSE_BUTTON_LEFT));
+
+        input.process_event(&WindowEvent::KeyDown {
+            scancode: 4,
+            keycode: 'a' as i32,
+            modifiers: KEYMOD_LSHIFT,
+            repeat: false,
+        });
+        input.process_event(&WindowEvent::FocusLost);
+        assert!(!input.is_key_held(4));
+        assert!(!input.is_keycode_held('a' as i32));
+        assert_eq!(input.modifiers(), KEYMOD_NONE);
+        assert!(!input.is_mouse_button_held(MOUSE_BUTTON_LEFT));
+        assert_eq!(input.mouse_position(), (15, 9));
+    }
+
+    #[test]
+    fn gamepad_state_test() {
+        let mut gamepads = GamepadState::new();
+
+        gamepads.process_event(&WindowEvent::ControllerAdded { id: 3 });
+        gamepads.process_event(&WindowEvent::ControllerButton {
+            id: 3,
+            button: CONTROLLER_BUTTON_A,
+            pressed: true,
+        });
+        gamepads.process_event(&WindowEvent::ControllerAxis {
+            id: 3,
+            axis: CONTROLLER_AXIS_LEFT_X,
+            value: i16::MIN,
+        });
+
+        let gamepad = gamepads.gamepad(3).unwrap();
+        assert!(gamepad.is_button_held(CONTROLLER_BUTTON_A));
+        assert!(!gamepad.is_button_held(CONTROLLER_BUTTON_B));
+        assert_eq!(gamepad.axis_normalized(CONTROLLER_AXIS_LEFT_X), -1.0);
+
+        gamepads.process_event(&WindowEvent::ControllerRemoved { id: 3 });
+        assert!(gamepads.gamepad(3).is_none());
+        assert!(gamepads.is_empty());
+    }
+}
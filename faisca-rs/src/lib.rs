@@ -1,15 +1,33 @@
+pub mod custom;
+mod event_hub;
 pub mod ffi;
+pub mod host;
+pub mod input;
 pub mod renderer;
+pub mod replay;
+pub mod request;
+#[cfg(feature = "png")]
+pub mod testing;
 mod util;
 
 pub use ash::vk;
 
-use std::sync::RwLock;
+use std::{
+    any::Any,
+    panic::{self, UnwindSafe},
+    sync::{PoisonError, RwLock},
+    time::Duration,
+};
 
+pub use custom::CustomMessage;
+pub use event_hub::EventSubscriber;
 pub use ffi::{
-    AppMessage, Fullscreen, MessageWindowFn, SafeCString, WindowEvent, WindowInstance,
-    WindowMessage,
+    AppMessage, DisplayMode, Fullscreen, MessageAppFn, MessageWindowFn, RunAppFn, SafeCString,
+    SystemCursor, WindowEvent, WindowInstance, WindowMessage,
 };
+pub use input::{GamepadState, InputState};
+pub use replay::{EventRecorder, EventSource, ReplayMessenger};
+pub use request::{Request, Response, ResponseError};
 
 #[cfg(debug_assertions)]
 pub const DEBUG_ENABLED: bool = true;
@@ -29,45 +47,197 @@ type WChanMsg = (ffi::WindowInstance, WindowEvent);
 
 pub struct WindowMessenger {
     messenger: MessageWindowFn,
-    wchan_recv: std::sync::mpsc::Receiver<WChanMsg>,
+    /// The handle of the event hub of the host.
+    hub: usize,
+    events: EventSubscriber,
 }
 
 impl WindowMessenger {
     pub unsafe fn from_raw(messenger: MessageWindowFn) -> Self {
-        let (wchan_send, wchan_recv) = std::sync::mpsc::channel();
+        // Every messenger of a host shares its hub, so this is fine to do
+        // more than once
+        let hub = event_hub::hub_for_host(messenger);
         let messenger = Self {
             messenger,
-            wchan_recv,
+            hub,
+            events: event_hub::subscribe(hub, Box::new(|_, _| true)),
         };
 
-        let wchan_send_box = Box::new(wchan_send);
         messenger.send(
             ffi::WindowInstance::null(),
             &AppMessage::SetMsgBackchannel {
-                channel: Box::into_raw(wchan_send_box) as *mut std::ffi::c_void,
+                channel: hub as *const std::ffi::c_void,
             },
         );
 
         messenger
     }
 
-    pub fn send(&self, w: ffi::WindowInstance, msg: &AppMessage) {
-        unsafe { (self.messenger)(w, msg as *const AppMessage) };
+    /// Sends a message to the host. Returns whether the host accepted it.
+    pub fn send(&self, w: ffi::WindowInstance, msg: &AppMessage) -> bool {
+        unsafe { (self.messenger)(w, msg as *const AppMessage) != 0 }
+    }
+
+    /// Sets the icon of the window from RGBA pixels, 8 bits per channel, row
+    /// by row from the top. Returns `false`, sending nothing, if `pixels` is
+    /// not `width * height` pixels, or if a side of the icon is zero or above
+    /// [MAX_WINDOW_ICON_SIDE](ffi::MAX_WINDOW_ICON_SIDE).
+    pub fn set_window_icon(
+        &self,
+        w: ffi::WindowInstance,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> bool {
+        if ffi::window_icon_len(width, height) != Some(pixels.len()) {
+            log::error!(
+                "Not setting window icon: {} bytes are not a {width}x{height} RGBA image",
+                pixels.len()
+            );
+            return false;
+        }
+
+        self.send(
+            w,
+            &AppMessage::SetWindowIcon {
+                width,
+                height,
+                pixels: pixels.as_ptr(),
+            },
+        )
+    }
+
+    /// Sends a request to the host, returning a handle to its answer.
+    pub fn request<R: Request>(&self, w: ffi::WindowInstance, request: R) -> Response<R::Output> {
+        Response::send(self, w, request)
+    }
+
+    /// Sends a custom message to a host plugin. Returns whether the host
+    /// accepted it.
+    pub fn send_custom<M: CustomMessage>(&self, w: ffi::WindowInstance, msg: &M) -> bool {
+        let payload = msg.encode();
+        self.send(
+            w,
+            &AppMessage::Custom {
+                kind: M::KIND,
+                payload: payload.as_ptr(),
+                len: payload.len(),
+            },
+        )
+    }
+
+    /// Creates another receiver for the events of the host, which only gets
+    /// the events `filter` accepts. It may be moved to another thread, like
+    /// the one of a debug overlay. The filter runs on the host thread, and
+    /// must not subscribe.
+    pub fn subscribe(
+        &self,
+        filter: impl Fn(ffi::WindowInstance, &WindowEvent) -> bool + Send + 'static,
+    ) -> EventSubscriber {
+        event_hub::subscribe(self.hub, Box::new(filter))
     }
 
     pub fn try_recv(&self) -> Option<WChanMsg> {
-        self.wchan_recv.try_recv().ok()
+        self.events.try_recv()
+    }
+
+    /// Blocks until an event arrives, or until `timeout` elapses. Lets an
+    /// app that has nothing to draw sleep until there is input.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<WChanMsg> {
+        self.events.recv_timeout(timeout)
+    }
+
+    /// Returns every event that already arrived, without blocking.
+    pub fn drain(&self) -> impl Iterator<Item = WChanMsg> + '_ {
+        self.events.drain()
+    }
+
+    /// See [EventSubscriber::set_coalesce_resizes].
+    pub fn set_coalesce_resizes(&mut self, enabled: bool) {
+        self.events.set_coalesce_resizes(enabled);
     }
 }
 
 static VK_INSTANCE_EXTENSIONS_VEC: RwLock<Vec<usize>> = RwLock::new(Vec::new());
 
+/// Set once the host says [Hello](WindowMessage::Hello) with a matching ABI
+/// version.
+static HOST_CAPABILITIES: RwLock<Option<u64>> = RwLock::new(None);
+
+/// The function to tell the host about panics with, set once the app runs.
+static HOST_MESSENGER: RwLock<Option<MessageWindowFn>> = RwLock::new(None);
+
+/// The `CAPABILITY_*` flags of the host, or `None` if the handshake with the
+/// host did not succeed (yet).
+pub fn host_capabilities() -> Option<u64> {
+    *HOST_CAPABILITIES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What [run_process] returns when the process function panics.
+pub const PROCESS_PANICKED: i32 = -1;
+
+/// Runs `f`, catching any panic so that it does not unwind into the host.
+/// The panic is logged, and the host notified with an
+/// [AppPanicked](AppMessage::AppPanicked).
+fn contain_panic<R>(entry_point: &str, f: impl FnOnce() -> R + UnwindSafe) -> Option<R> {
+    match panic::catch_unwind(f) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            let message = format!("Panicked on {entry_point}: {}", panic_message(&*payload));
+            log::error!("{message}");
+
+            let message_window = *HOST_MESSENGER
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let (Some(message_window), Ok(message)) =
+                (message_window, SafeCString::allocate_from_str(&message))
+            {
+                unsafe {
+                    message_window(
+                        ffi::WindowInstance::null(),
+                        &AppMessage::AppPanicked { message },
+                    )
+                };
+            }
+            None
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
 pub unsafe fn run_app(
     w: ffi::WindowInstance,
     message_window: ffi::MessageWindowFn,
     entry_fn: impl FnOnce(ffi::WindowInstance, WindowMessenger) + std::panic::UnwindSafe,
 ) {
-    entry_fn(w, WindowMessenger::from_raw(message_window));
+    if host_capabilities().is_none() {
+        log::error!("Refusing to run the app: the host did not complete the ABI handshake");
+        return;
+    }
+
+    *HOST_MESSENGER
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(message_window);
+    contain_panic("faisca_run_app", || {
+        entry_fn(w, WindowMessenger::from_raw(message_window))
+    });
+}
+
+/// Runs the process function of the app, returning [PROCESS_PANICKED] if it
+/// panics.
+pub fn run_process(process_fn: impl FnOnce() -> i32 + UnwindSafe) -> i32 {
+    contain_panic("faisca_process", process_fn).unwrap_or(PROCESS_PANICKED)
 }
 
 #[macro_export]
@@ -88,47 +258,210 @@ macro_rules! app_process {
     ($process_fn:ident) => {
         #[no_mangle]
         pub unsafe extern "C" fn faisca_process() -> i32 {
-            $process_fn()
+            $crate::run_process($process_fn)
+        }
+    };
+}
+
+/// Exports the functions a host calls to hand the app state over to a
+/// reloaded app library. `$save_fn` returns the state as bytes, which are
+/// later given to `$load_fn` in the reloaded library, before the app runs
+/// again. Both run on the host thread.
+#[macro_export]
+macro_rules! app_state {
+    ($save_fn:ident, $load_fn:ident) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn faisca_save_state(
+            sink: $crate::ffi::StateSinkFn,
+            user: *mut ::std::ffi::c_void,
+        ) -> u32 {
+            $crate::save_state($save_fn, sink, user)
+        }
+
+        #[no_mangle]
+        pub unsafe extern "C" fn faisca_load_state(data: *const u8, len: usize) -> u32 {
+            $crate::load_state($load_fn, data, len)
         }
     };
 }
 
+/// Hands the state `save_fn` returns to the host through `sink`. Returns
+/// [STATE_FAILED](ffi::STATE_FAILED) if `save_fn` panics.
+///
+/// # Safety
+/// `sink` must be safe to call with `user`.
+pub unsafe fn save_state(
+    save_fn: impl FnOnce() -> Vec<u8> + UnwindSafe,
+    sink: ffi::StateSinkFn,
+    user: *mut std::ffi::c_void,
+) -> u32 {
+    match contain_panic("faisca_save_state", save_fn) {
+        Some(state) => {
+            sink(user, state.as_ptr(), state.len());
+            ffi::STATE_OK
+        }
+        None => ffi::STATE_FAILED,
+    }
+}
+
+/// Hands the state the host kept to `load_fn`. Returns
+/// [STATE_FAILED](ffi::STATE_FAILED) if `load_fn` panics.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, unless `len` is zero.
+pub unsafe fn load_state(
+    load_fn: impl FnOnce(&[u8]) + UnwindSafe,
+    data: *const u8,
+    len: usize,
+) -> u32 {
+    let state = request::raw_slice(data, len);
+
+    match contain_panic("faisca_load_state", || load_fn(state)) {
+        Some(()) => ffi::STATE_OK,
+        None => ffi::STATE_FAILED,
+    }
+}
+
 // #[no_mangle]
 // pub unsafe extern "C" fn faisca_init_renderer(wstate: *const ffi::WState) {}
 
+/// Lets a host check the app was built against the same message layouts
+/// before sending it anything.
+#[no_mangle]
+pub extern "C" fn faisca_abi_version() -> u32 {
+    ffi::ABI_VERSION
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn faisca_message_app(
     w: ffi::WindowInstance,
     msg: *const WindowMessage,
 ) -> u32 {
+    contain_panic("faisca_message_app", || unsafe { message_app(w, msg) })
+        .unwrap_or(ffi::MESSAGE_PANICKED)
+}
+
+unsafe fn message_app(w: ffi::WindowInstance, msg: *const WindowMessage) -> u32 {
+    // Until the handshake succeeds, we can't trust the message layout, not
+    // even the discriminant. Only the `Hello` layout is the same everywhere.
+    let msg_type = *(msg as *const u32);
+    if msg_type == 0 {
+        let WindowMessage::Hello {
+            abi_version,
+            capabilities,
+        } = *msg
+        else {
+            unreachable!()
+        };
+
+        if abi_version != ffi::ABI_VERSION {
+            log::error!(
+                "The host uses ABI version {abi_version}, but the app was built for version {}. \
+                 Both must be built from the same Faisca version",
+                ffi::ABI_VERSION
+            );
+            return ffi::MESSAGE_REJECTED_ABI_MISMATCH;
+        }
+
+        *HOST_CAPABILITIES
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(capabilities);
+        return ffi::MESSAGE_ACCEPTED;
+    }
+
+    if host_capabilities().is_none() {
+        log::error!("Rejecting message of type {msg_type}: the host did not say hello first");
+        return ffi::MESSAGE_REJECTED_NO_HANDSHAKE;
+    }
+
     match *msg {
+        // Handled above
+        WindowMessage::Hello { .. } => (),
         WindowMessage::VulkanRequiredInstanceExtensions { names, count } => {
             let names = names as *const usize;
             let names = std::slice::from_raw_parts(names, count).to_vec();
             let mut write_guard = VK_INSTANCE_EXTENSIONS_VEC.write().unwrap();
             *write_guard = names;
         }
-        WindowMessage::ResponseNotify { binding_address } => {
-            unsafe { &*binding_address }.notify();
+        WindowMessage::ResponseNotify {
+            binding_address,
+            status,
+            data,
+        } => {
+            ffi::ResponseBinding::complete(binding_address, status, data);
         }
         WindowMessage::WindowEvent { channel, event } => {
-            if channel.is_null() {
+            // The host owns the event, along with any string it carries, so
+            // subscribers get copies of it
+            if !event_hub::publish(channel as usize, w, &*event) {
                 log::warn!("Dropping window event: channel not set");
-            } else {
-                let channel =
-                    unsafe { Box::from_raw(channel as *mut std::sync::mpsc::Sender<WChanMsg>) };
-
-                let event = unsafe { *event };
-                let _ = channel.send((w, event));
-                match event {
-                    WindowEvent::Quit => (),
-                    _ => {
-                        // If it is not a Quit event, we don't want to call the drop
-                        Box::leak(channel);
-                    }
-                }
             }
         }
+        WindowMessage::Custom { kind, payload, len } => {
+            if !custom::dispatch(w, kind, request::raw_slice(payload, len)) {
+                return ffi::MESSAGE_UNHANDLED;
+            }
+        }
+    }
+    ffi::MESSAGE_ACCEPTED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn abi_handshake_test() {
+        let hello = WindowMessage::Hello {
+            abi_version: ffi::ABI_VERSION + 1,
+            capabilities: 0,
+        };
+        assert_eq!(
+            unsafe { faisca_message_app(WindowInstance::null(), &hello) },
+            ffi::MESSAGE_REJECTED_ABI_MISMATCH
+        );
+        assert_eq!(faisca_abi_version(), ffi::ABI_VERSION);
+    }
+
+    unsafe extern "C" fn vec_sink(user: *mut std::ffi::c_void, data: *const u8, len: usize) {
+        let saved = &mut *(user as *mut Vec<u8>);
+        saved.extend_from_slice(std::slice::from_raw_parts(data, len));
+    }
+
+    #[test]
+    fn state_handoff_test() {
+        let mut saved = Vec::new();
+        let status = unsafe {
+            save_state(
+                || vec![1, 2, 3],
+                vec_sink,
+                &mut saved as *mut Vec<u8> as *mut std::ffi::c_void,
+            )
+        };
+        assert_eq!(status, ffi::STATE_OK);
+        assert_eq!(saved, [1, 2, 3]);
+
+        let loaded = std::sync::Mutex::new(Vec::new());
+        let status = unsafe {
+            load_state(
+                |state| *loaded.lock().unwrap() = state.to_vec(),
+                saved.as_ptr(),
+                saved.len(),
+            )
+        };
+        assert_eq!(status, ffi::STATE_OK);
+        assert_eq!(*loaded.lock().unwrap(), saved);
+
+        let status = unsafe { load_state(|_| panic!("bad state"), std::ptr::null(), 0) };
+        assert_eq!(status, ffi::STATE_FAILED);
+    }
+
+    #[test]
+    fn panic_containment_test() {
+        assert_eq!(run_process(|| 7), 7);
+        assert_eq!(run_process(|| panic!("process failed")), PROCESS_PANICKED);
+
+        let payload = panic::catch_unwind(|| panic!("failed with code {}", 3)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "failed with code 3");
     }
-    0
 }
@@ -277,7 +277,7 @@ impl BufferManager {
         }
     }
 
-    fn find_memory_type(
+    pub fn find_memory_type(
         mem_properties: vk::PhysicalDeviceMemoryProperties,
         type_filter: u32,
         property_flags: vk::MemoryPropertyFlags,
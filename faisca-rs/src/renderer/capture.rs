@@ -0,0 +1,66 @@
+use ash::vk;
+
+/// A frame copied back from the GPU by
+/// [capture_next_frame](super::Renderer::capture_next_frame), as tightly
+/// packed RGBA8 rows, top to bottom. The colors are left as they were drawn,
+/// so frames of an sRGB swapchain hold sRGB encoded colors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// A host visible buffer images get copied to, so that the CPU can read
+/// them.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ReadbackBuffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+}
+
+#[cfg(feature = "png")]
+impl CapturedFrame {
+    /// Encodes the frame as a PNG image.
+    pub fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut png_data = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut png_data, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+
+        Ok(png_data)
+    }
+
+    /// Encodes the frame as a PNG image, and writes it to `path`.
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let png_data = self.encode_png().map_err(std::io::Error::other)?;
+        std::fs::write(path, png_data)
+    }
+}
+
+#[cfg(all(test, feature = "png"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_encoding_test() {
+        let frame = CapturedFrame {
+            width: 2,
+            height: 1,
+            pixels: vec![255, 0, 0, 255, 0, 128, 255, 64],
+        };
+        let png_data = frame.encode_png().unwrap();
+
+        let mut reader = png::Decoder::new(png_data.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (2, 1));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(pixels, frame.pixels);
+    }
+}
@@ -0,0 +1,23 @@
+use super::buffer::VirtualBuffer;
+
+/// Identifies a mesh created with
+/// [create_mesh](super::Renderer::create_mesh). Handles are never reused, so
+/// the handle of a destroyed mesh stays invalid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MeshHandle(u64);
+
+impl MeshHandle {
+    #[inline]
+    pub(super) fn new(id: u64) -> Self {
+        Self(id)
+    }
+}
+
+/// The GPU buffers of a mesh.
+pub struct Mesh {
+    pub vertex_buffer: VirtualBuffer,
+    pub index_buffer: VirtualBuffer,
+    /// How many indices to draw, which the buffer sizes don't tell, as they
+    /// may be rounded up.
+    pub index_count: u32,
+}
@@ -1,11 +1,21 @@
-use crate::{ffi::ResponseBinding, util::OnDropDefer, AppMessage, WindowInstance, WindowMessenger};
+use crate::{
+    request::{self, ResponseError},
+    util::OnDropDefer,
+    WindowInstance, WindowMessenger,
+};
 use ash::{
     extensions::{ext, khr},
-    vk::{self, Handle, MemoryPropertyFlags},
+    vk::{self, MemoryPropertyFlags},
 };
-use std::{ffi::CStr, mem::MaybeUninit};
+use std::{collections::BTreeMap, ffi::CStr, time::Duration};
+
+use self::{capture::ReadbackBuffer, mesh::Mesh, resources::RendererResourceKeeper, utypes::*};
 
-use self::{buffer::VirtualBuffer, resources::RendererResourceKeeper, utypes::*};
+pub use self::{
+    capture::CapturedFrame,
+    mesh::MeshHandle,
+    vertex::{Point2DColorRGBVertex, Vector2, Vector3},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum RendererError {
@@ -24,8 +34,10 @@ pub enum RendererError {
     #[error("The program requires some validation layers that are not available")]
     UnavailableValidationLayers(Box<[*const i8]>),
 
-    #[error("Failed to create Vulkan surface")]
-    FailedToCreateVulkanSurface,
+    #[error("Failed to create Vulkan surface: {0}")]
+    FailedToCreateVulkanSurface(ResponseError),
+    #[error("The window host failed to answer a request: {0}")]
+    WindowRequestFailed(#[from] ResponseError),
     #[error("Failed to find a video adapter (GPU) supporting Vulkan")]
     NoAvailableVideoAdapter,
     #[error("Failed to find a video adapter (GPU) that this application supports")]
@@ -74,26 +86,54 @@ pub enum RendererError {
 
     #[error("Failed to draw Vulkan frame, Vulkan error code: {0}")]
     FailedToDrawFrame(vk::Result),
+
+    #[error("The renderer is not attached to the window")]
+    UnknownWindow,
+    #[error("The renderer is already attached to the window")]
+    WindowAlreadyAttached,
+    #[error("The selected video adapter cannot present to the window surface")]
+    UnsupportedSurface,
+    #[error("The window surface does not support the format the renderer draws in")]
+    IncompatibleSurfaceFormat,
+
+    #[error("Offscreen rendering needs a format with four 8 bit channels, got {0:?}")]
+    UnsupportedOffscreenFormat(vk::Format),
+    #[error("Offscreen rendering needs a non-zero extent, got {0:?}")]
+    InvalidOffscreenExtent(vk::Extent2D),
+    #[error("Failed to create Vulkan image, Vulkan error code: {0}")]
+    FailedToCreateImage(vk::Result),
+    #[error("The renderer draws offscreen and cannot present to windows")]
+    OffscreenRenderer,
+    #[error("The renderer draws to windows, there is no offscreen image to read")]
+    NotOffscreen,
+    #[error("The window surface does not allow copying its images, frames cannot be captured")]
+    CaptureUnsupported,
+    #[error("Frames in {0:?} cannot be captured as RGBA8")]
+    UnsupportedCaptureFormat(vk::Format),
+
+    #[error("A mesh needs at least one vertex and one index")]
+    EmptyMesh,
+    #[error("The mesh has an index to vertex {index}, but only {vertex_count} vertices")]
+    MeshIndexOutOfRange { index: u16, vertex_count: usize },
+    #[error("The mesh does not exist, or was destroyed")]
+    UnknownMesh,
 }
 
 mod buffer;
+mod capture;
+mod mesh;
+mod offscreen_target;
 mod queue;
 mod resources;
+mod surface_target;
 mod swapchain_info;
 pub mod utypes;
 mod vertex;
 
 const MAX_CONCURRENT_FRAMES: usize = 2;
 
-use vertex::{Point2DColorRGBVertex, Vector2, Vector3};
-
-#[rustfmt::skip]
-static VERTICES: [Point2DColorRGBVertex; 4] = [
-    Point2DColorRGBVertex { point: Vector2([ 0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
-    Point2DColorRGBVertex { point: Vector2([ 0.5,  0.5]), color: Vector3([ 0.0,  1.0,  0.0]) },
-    Point2DColorRGBVertex { point: Vector2([-0.5,  0.5]), color: Vector3([ 0.0,  0.0,  1.0]) },
-    Point2DColorRGBVertex { point: Vector2([-0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
-];
+/// How long we wait for the window host to answer a request before giving up.
+const HOST_RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct Renderer {
     vk_res: RendererResourceKeeper,
@@ -101,15 +141,33 @@ pub struct Renderer {
     entry: ash::Entry,
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
-    swapchain_img_extent: vk::Extent2D,
-    command_buffers: Vec<vk::CommandBuffer>,
-    current_frame: usize,
+    /// The window the renderer was created for. The methods without a window
+    /// parameter act on it.
+    main_window: WindowInstance,
+    /// The format the render pass was created for. Every window surface must
+    /// support it.
+    swapchain_img_format: vk::SurfaceFormatKHR,
+
+    /// The window whose next frame gets captured.
+    pending_capture: Option<WindowInstance>,
+    captured_frame: Option<CapturedFrame>,
+
+    /// Drawn in creation order, every frame.
+    meshes: BTreeMap<MeshHandle, Mesh>,
+    next_mesh_id: u64,
 
-    test_vertex_buffer: VirtualBuffer,
-    test_index_buffer: VirtualBuffer,
     test_ubo: StandardUBO,
 }
 
+/// An image to copy to a readback buffer once drawn to.
+#[derive(Clone, Copy)]
+struct ImageReadback {
+    image: vk::Image,
+    /// The layout the image is in, and must be left in.
+    layout: vk::ImageLayout,
+    buffer: vk::Buffer,
+}
+
 impl Renderer {
     pub fn new(
         window: WindowInstance,
@@ -120,6 +178,208 @@ impl Renderer {
 
         let mut vk_res = RendererResourceKeeper::new();
 
+        // The host knows which surface extensions its windows need
+        let host_extensions = crate::VK_INSTANCE_EXTENSIONS_VEC
+            .read()
+            .unwrap()
+            .iter()
+            .map(|&usize_ptr_rep| usize_ptr_rep as *const i8)
+            .collect();
+        Self::create_instance(&entry, &mut vk_res, host_extensions, false)?;
+
+        unsafe {
+            *vk_res.surface_loader_mut() = Some(khr::Surface::new(&entry, vk_res.instance()));
+        }
+
+        let surface = Self::request_surface(&vk_res, window, messenger)?;
+        let main_target = vk_res.add_target(window, surface);
+
+        let selected_physical_device =
+            Self::select_physical_device(vk_res.instance(), vk_res.surface_loader(), surface)?;
+
+        unsafe {
+            *vk_res.physical_device_mut() = selected_physical_device;
+        }
+
+        // With this, for some physical device, we store the queue indices for the
+        // queue falimies we want to use (graphics and present). They may have the
+        // same index (be the same family).
+        let queue_indices = queue::QueueFamilyIndices::fetch(
+            vk_res.instance(),
+            vk_res.surface_loader(),
+            surface,
+            selected_physical_device,
+        );
+
+        let device = Self::create_device(
+            &entry,
+            vk_res.instance(),
+            &queue_indices,
+            selected_physical_device,
+            &crate::VK_REQUIRED_DEVICE_EXTENSIONS,
+            false,
+        )?;
+        unsafe { *vk_res.device_mut() = Some(device) };
+
+        let graphics_queue = unsafe {
+            vk_res
+                .device()
+                .get_device_queue(queue_indices.graphics_family.unwrap(), 0)
+        };
+
+        let present_queue = unsafe {
+            vk_res
+                .device()
+                .get_device_queue(queue_indices.present_family.unwrap(), 0)
+        };
+
+        unsafe { *vk_res.queue_families_mut() = queue_indices };
+
+        let swapchain_info = swapchain_info::SwapchainSupportInfo::fetch(
+            vk_res.surface_loader(),
+            surface,
+            selected_physical_device,
+        )
+        .unwrap();
+
+        unsafe {
+            *vk_res.swapchain_loader_mut() =
+                Some(khr::Swapchain::new(vk_res.instance(), vk_res.device()));
+        }
+
+        let window_extent = Self::request_viewport_extent(window, messenger)?;
+        let swapchain_img_extent = swapchain_info.select_extent(window_extent);
+        let swapchain_img_format = swapchain_info.select_format().unwrap();
+
+        unsafe {
+            *vk_res.render_pass_mut() = Self::create_render_pass(
+                vk_res.device(),
+                swapchain_img_format.format,
+                // We want the images ready for presenting when we render them
+                vk::ImageLayout::PRESENT_SRC_KHR,
+            )?;
+        }
+
+        vk_res.create_swapchain(main_target, &swapchain_info, swapchain_img_extent)?;
+        vk_res.target_mut(main_target).window_extent = window_extent;
+
+        Self::create_pipeline_and_command_pools(&mut vk_res, swapchain_img_extent)?;
+
+        unsafe { vk_res.create_frame_objects(main_target, MAX_CONCURRENT_FRAMES)? };
+
+        Ok(Renderer {
+            entry,
+            vk_res,
+            graphics_queue,
+            present_queue,
+            main_window: window,
+            swapchain_img_format,
+
+            pending_capture: None,
+            captured_frame: None,
+
+            meshes: BTreeMap::new(),
+            next_mesh_id: 0,
+
+            test_ubo: StandardUBO::default(),
+        })
+    }
+
+    /// Creates a renderer that draws to an image of its own rather than to a
+    /// window, so it needs neither a host nor a surface. Read the frames back
+    /// with [read_offscreen_pixels](Renderer::read_offscreen_pixels).
+    ///
+    /// `format` must have four 8 bit channels, in RGBA or BGRA order.
+    pub fn new_offscreen(
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Renderer, RendererError> {
+        if !offscreen_target::is_rgba8_compatible(format) {
+            return Err(RendererError::UnsupportedOffscreenFormat(format));
+        }
+        if extent.width == 0 || extent.height == 0 {
+            return Err(RendererError::InvalidOffscreenExtent(extent));
+        }
+
+        let entry = unsafe { ash::Entry::load()? };
+
+        let mut vk_res = RendererResourceKeeper::new();
+
+        // No window, so no surface extensions. This runs on test machines
+        // too, which often lack the validation layers, so debugging is
+        // optional
+        Self::create_instance(&entry, &mut vk_res, Vec::new(), true)?;
+
+        let selected_physical_device =
+            Self::select_offscreen_physical_device(vk_res.instance(), format)?;
+        unsafe { *vk_res.physical_device_mut() = selected_physical_device };
+
+        let queue_indices =
+            queue::QueueFamilyIndices::fetch_offscreen(vk_res.instance(), selected_physical_device);
+
+        let device = Self::create_device(
+            &entry,
+            vk_res.instance(),
+            &queue_indices,
+            selected_physical_device,
+            &[],
+            true,
+        )?;
+        unsafe { *vk_res.device_mut() = Some(device) };
+
+        // There is nothing to present, the graphics queue does it all
+        let graphics_queue = unsafe {
+            vk_res
+                .device()
+                .get_device_queue(queue_indices.graphics_family.unwrap(), 0)
+        };
+
+        unsafe { *vk_res.queue_families_mut() = queue_indices };
+
+        unsafe {
+            *vk_res.render_pass_mut() = Self::create_render_pass(
+                vk_res.device(),
+                format,
+                // The image gets copied out once rendered
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            )?;
+        }
+
+        Self::create_pipeline_and_command_pools(&mut vk_res, extent)?;
+
+        unsafe { vk_res.create_offscreen_target(format, extent)? };
+
+        Ok(Renderer {
+            entry,
+            vk_res,
+            graphics_queue,
+            present_queue: graphics_queue,
+            main_window: unsafe { WindowInstance::null() },
+            swapchain_img_format: vk::SurfaceFormatKHR {
+                format,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+
+            pending_capture: None,
+            captured_frame: None,
+
+            meshes: BTreeMap::new(),
+            next_mesh_id: 0,
+
+            test_ubo: StandardUBO::default(),
+        })
+    }
+
+    /// Creates the Vulkan instance, enabling `required_ext` on it, along with
+    /// the debug messenger in debug mode. With `debugging_optional`, missing
+    /// validation layers or debug utils are logged and done without, rather
+    /// than failing.
+    fn create_instance(
+        entry: &ash::Entry,
+        vk_res: &mut RendererResourceKeeper,
+        required_ext: Vec<*const i8>,
+        debugging_optional: bool,
+    ) -> Result<(), RendererError> {
         let app_info = vk::ApplicationInfo {
             p_application_name: b"Faisca App\0" as *const u8 as *const i8,
             application_version: vk::make_api_version(0, 1, 0, 0),
@@ -132,11 +392,15 @@ impl Renderer {
 
         // Instance extensions are driver extensions that are useful independently
         // of any specific device
-        let instance_extensions_array = Renderer::get_instance_extensions(&entry)?;
+        let debug_utils_enabled = crate::DEBUG_ENABLED
+            && (!debugging_optional || Renderer::is_debug_utils_available(entry)?);
+        let instance_extensions_array =
+            Renderer::get_instance_extensions(entry, required_ext, debug_utils_enabled)?;
         // Validation layers are like extensions, but used to make debugging
         // simpler, as well as giving warnings in case we do something outside
         // of what is allowed by the Vulkan specification.
-        let validation_layers_array = Renderer::get_validation_layers(&entry)?;
+        let validation_layers_array =
+            Renderer::get_validation_layers_for(entry, debugging_optional)?;
 
         let debug_messenger_info = vk::DebugUtilsMessengerCreateInfoEXT {
             // We will accept messages of these severities...
@@ -159,11 +423,8 @@ impl Renderer {
 
         let instance_info = vk::InstanceCreateInfo {
             p_application_info: &app_info,
-            // This application cannot work without at least a surface extension
-            // (because we need to display to a window). This means we don't check
-            // if `instance_extensions_array` is empty or not, as the application
-            // would've ran into an error erlier if that were the case. The function
-            // `get_instance_extensions` called earlier makes the check.
+            // Drawing offscreen in release mode, this list can be empty, in
+            // which case the pointer is never read.
             pp_enabled_extension_names: instance_extensions_array.as_ptr(),
             enabled_extension_count: instance_extensions_array.len().try_into().unwrap(),
             // We enable layers only if there are layers to be enabled
@@ -175,7 +436,7 @@ impl Renderer {
             enabled_layer_count: validation_layers_array.len().try_into().unwrap(),
             // In debug mode, we set an extra header so that we can debug the
             // creation and destruction of the instance.
-            p_next: if crate::DEBUG_ENABLED {
+            p_next: if debug_utils_enabled {
                 &debug_messenger_info as *const vk::DebugUtilsMessengerCreateInfoEXT
                     as *const std::ffi::c_void
             } else {
@@ -197,15 +458,14 @@ impl Renderer {
         log::debug!("Vulkan Instance created");
 
         unsafe {
-            *vk_res.surface_loader_mut() = Some(khr::Surface::new(&entry, vk_res.instance()));
-            *vk_res.debug_loader_mut() = Some(ext::DebugUtils::new(&entry, vk_res.instance()));
-        };
+            *vk_res.debug_loader_mut() = Some(ext::DebugUtils::new(entry, vk_res.instance()));
+        }
 
         // In debug mode, we make a debug messenger, that will give us feedback,
         // specially about all that validation thing we talked about earlier.
         // This is how the driver tells us a bit about what it is doing on its
         // end, and gives feedback about how badly we're working on our end.
-        let debug_messenger = if crate::DEBUG_ENABLED {
+        let debug_messenger = if debug_utils_enabled {
             let messenger = unsafe {
                 vk_res
                     .debug_loader()
@@ -220,104 +480,15 @@ impl Renderer {
 
         unsafe { *vk_res.debug_messenger_mut() = debug_messenger };
 
-        // Here we basically ask the Window for a SurfaceKHR handle. The window code
-        // will do something platform-specific in order to acquire this handle for us.
-        let mut surface: MaybeUninit<u64> = MaybeUninit::uninit();
-        let binding =
-            unsafe { ResponseBinding::new(surface.as_mut_ptr() as *mut std::ffi::c_void) };
-        messenger.send(
-            window,
-            &AppMessage::CreateVulkanSurface {
-                instance: vk_res.instance().handle().as_raw(),
-                out_binding: &binding as *const ResponseBinding,
-            },
-        );
-        binding.wait();
-
-        unsafe {
-            *vk_res.surface_mut() = vk::SurfaceKHR::from_raw(surface.assume_init());
-        }
-        // It is possible that the handle creation fails, in which case we receive a
-        // null handle.
-        if vk_res.surface() == vk::SurfaceKHR::null() {
-            return Err(RendererError::FailedToCreateVulkanSurface);
-        }
-
-        let selected_physical_device = Self::select_physical_device(
-            vk_res.instance(),
-            vk_res.surface_loader(),
-            vk_res.surface(),
-        )?;
-
-        unsafe {
-            *vk_res.physical_device_mut() = selected_physical_device;
-        }
-
-        // With this, for some physical device, we store the queue indices for the
-        // queue falimies we want to use (graphics and present). They may have the
-        // same index (be the same family).
-        let queue_indices = queue::QueueFamilyIndices::fetch(
-            vk_res.instance(),
-            vk_res.surface_loader(),
-            vk_res.surface(),
-            selected_physical_device,
-        );
-
-        let device = Self::create_device(
-            &entry,
-            vk_res.instance(),
-            &queue_indices,
-            selected_physical_device,
-        )?;
-        unsafe { *vk_res.device_mut() = Some(device) };
-
-        let graphics_queue = unsafe {
-            vk_res
-                .device()
-                .get_device_queue(queue_indices.graphics_family.unwrap(), 0)
-        };
-
-        let present_queue = unsafe {
-            vk_res
-                .device()
-                .get_device_queue(queue_indices.present_family.unwrap(), 0)
-        };
-
-        unsafe { *vk_res.queue_families_mut() = queue_indices };
-
-        let swapchain_info = swapchain_info::SwapchainSupportInfo::fetch(
-            vk_res.surface_loader(),
-            vk_res.surface(),
-            selected_physical_device,
-        )
-        .unwrap();
-
-        unsafe {
-            *vk_res.swapchain_loader_mut() =
-                Some(khr::Swapchain::new(vk_res.instance(), vk_res.device()));
-        }
-
-        let mut extent = MaybeUninit::<vk::Extent2D>::uninit();
-        let out_binding =
-            unsafe { ResponseBinding::new(extent.as_mut_ptr() as *mut std::ffi::c_void) };
-        messenger.send(
-            window,
-            &AppMessage::QueryViewportExtents {
-                out_binding: &out_binding as *const ResponseBinding,
-            },
-        );
-        out_binding.wait();
-
-        let swapchain_img_extent = swapchain_info.select_extent(unsafe { extent.assume_init() });
-        let swapchain_img_format = swapchain_info.select_format().unwrap();
-
-        unsafe {
-            *vk_res.render_pass_mut() =
-                Self::create_render_pass(vk_res.device(), swapchain_img_format.format)?;
-        }
-
-        vk_res.create_swapchain(&swapchain_info, swapchain_img_extent)?;
+        Ok(())
+    }
 
+    /// Creates what drawing needs besides the render pass, which must exist
+    /// already, and the targets: the pipeline and the command pools.
+    fn create_pipeline_and_command_pools(
+        vk_res: &mut RendererResourceKeeper,
+        viewport_extent: vk::Extent2D,
+    ) -> Result<(), RendererError> {
         let descriptor_set_layout_info = vk::DescriptorSetLayoutCreateInfo {
             binding_count: 1,
             p_bindings: &[StandardUBO::uniform_buffer_binding(0)] as *const _,
@@ -333,7 +504,7 @@ impl Renderer {
         unsafe {
             let (pipeline, pipeline_layout) = Self::create_graphics_pipeline(
                 vk_res.device(),
-                swapchain_img_extent,
+                viewport_extent,
                 vk_res.render_pass(),
             )?;
             *vk_res.pipeline_mut() = pipeline_layout;
@@ -342,7 +513,7 @@ impl Renderer {
 
         let command_pool_info = vk::CommandPoolCreateInfo {
             flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-            queue_family_index: queue_indices.graphics_family.unwrap(),
+            queue_family_index: vk_res.queue_families().graphics_family.unwrap(),
             ..Default::default()
         };
         unsafe {
@@ -353,7 +524,7 @@ impl Renderer {
         }
 
         // Try making a dedicated transfer command pool
-        if let Some(dedicated_family) = queue_indices.dedicated_transfer_family {
+        if let Some(dedicated_family) = vk_res.queue_families().dedicated_transfer_family {
             let command_pool_info = vk::CommandPoolCreateInfo {
                 flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
                 queue_family_index: dedicated_family,
@@ -367,59 +538,52 @@ impl Renderer {
             }
         }
 
-        let command_buffer_info = vk::CommandBufferAllocateInfo {
-            command_pool: vk_res.command_pool(),
-            level: vk::CommandBufferLevel::PRIMARY,
-            command_buffer_count: MAX_CONCURRENT_FRAMES.try_into().unwrap(),
-            ..Default::default()
-        };
-        let command_buffers = unsafe {
-            vk_res
-                .device()
-                .allocate_command_buffers(&command_buffer_info)
-        }
-        .map_err(RendererError::FailedToCreateCommandBuffer)?;
-
-        unsafe { vk_res.create_sync_objects(MAX_CONCURRENT_FRAMES)? };
-
-        let vertex_data_len = VERTICES
-            .len()
-            .checked_mul(std::mem::size_of::<Point2DColorRGBVertex>())
-            .unwrap();
+        Ok(())
+    }
 
-        let test_vertex_buffer = unsafe {
-            let vertex_data =
-                std::slice::from_raw_parts(VERTICES.as_ptr() as *const u8, vertex_data_len);
-            vk_res.create_vertex_vbuffer(vertex_data)?
-        };
-        let test_index_buffer = unsafe {
-            let indices = [0, 1, 2, 2, 3, 0u16];
-            vk_res.create_index_vbuffer(&indices)?
+    /// Here we basically ask the Window for a SurfaceKHR handle. The window code
+    /// will do something platform-specific in order to acquire this handle for us.
+    fn request_surface(
+        vk_res: &RendererResourceKeeper,
+        window: WindowInstance,
+        messenger: &WindowMessenger,
+    ) -> Result<vk::SurfaceKHR, RendererError> {
+        let request = request::CreateVulkanSurface {
+            instance: vk_res.instance().handle(),
         };
+        messenger
+            .request(window, request)
+            .wait_timeout(HOST_RESPONSE_TIMEOUT)
+            .map_err(RendererError::FailedToCreateVulkanSurface)
+    }
 
-        Ok(Renderer {
-            entry,
-            vk_res,
-            graphics_queue,
-            present_queue,
-            swapchain_img_extent,
-            command_buffers,
-            current_frame: 0,
-
-            test_vertex_buffer,
-            test_index_buffer,
-            test_ubo: StandardUBO::default(),
+    fn request_viewport_extent(
+        window: WindowInstance,
+        messenger: &WindowMessenger,
+    ) -> Result<vk::Extent2D, RendererError> {
+        let extent = messenger
+            .request(window, request::QueryViewportExtents)
+            .wait_timeout(HOST_RESPONSE_TIMEOUT)?;
+
+        Ok(vk::Extent2D {
+            width: extent.width,
+            height: extent.height,
         })
     }
 
     /// This function checks whether or not the driver supports the instance
-    /// extensions we required, returning an error otherwise. In case the driver
+    /// extensions we required (`required_ext`, plus debug utils if
+    /// `debug_utils` is set), returning an error otherwise. In case the driver
     /// supports the extensions, we return the extension list so that it can be
     /// used to initialize the driver.
     ///
     /// This function also returns an error in case it fails to query the driver
     /// about supported extensions.
-    fn get_instance_extensions(entry: &ash::Entry) -> Result<Box<[*const i8]>, RendererError> {
+    fn get_instance_extensions(
+        entry: &ash::Entry,
+        mut required_ext: Vec<*const i8>,
+        debug_utils: bool,
+    ) -> Result<Box<[*const i8]>, RendererError> {
         let properties = entry
             .enumerate_instance_extension_properties(None)
             .map_err(RendererError::VulkanInfoQueryFailed)?;
@@ -433,16 +597,11 @@ impl Renderer {
             log::debug!("Available Vulkan extensions:{ext_names}");
         }
 
-        let mut required_ext: Vec<*const i8> = crate::VK_INSTANCE_EXTENSIONS_VEC
-            .read()
-            .unwrap()
-            .iter()
-            .map(|&usize_ptr_rep| usize_ptr_rep as *const i8)
-            .collect();
-
-        if crate::DEBUG_ENABLED {
+        if debug_utils {
             required_ext.push(crate::VK_EXT_DEBUG_UTILS_EXTENSION_NAME.as_ptr() as *const i8);
+        }
 
+        if crate::DEBUG_ENABLED {
             let mut ext_names = String::new();
             for ext in required_ext.iter().cloned() {
                 let ext_name = unsafe { CStr::from_ptr(ext) }.to_string_lossy();
@@ -454,6 +613,43 @@ impl Renderer {
         Ok(required_ext.into_boxed_slice())
     }
 
+    /// Whether the driver supports the debug utils instance extension, which
+    /// the debug messenger needs. Logs a warning if it does not.
+    fn is_debug_utils_available(entry: &ash::Entry) -> Result<bool, RendererError> {
+        let debug_utils = CStr::from_bytes_with_nul(crate::VK_EXT_DEBUG_UTILS_EXTENSION_NAME)
+            .expect("The extension name is NUL terminated");
+        let available = entry
+            .enumerate_instance_extension_properties(None)
+            .map_err(RendererError::VulkanInfoQueryFailed)?
+            .iter()
+            .any(|prop| unsafe { CStr::from_ptr(prop.extension_name.as_ptr()) } == debug_utils);
+
+        if !available {
+            log::warn!("VK_EXT_debug_utils is not available, going without the debug messenger");
+        }
+        Ok(available)
+    }
+
+    /// Like [get_validation_layers](Self::get_validation_layers), but with
+    /// `optional` set, missing layers are logged and the available ones
+    /// enabled, rather than failing.
+    fn get_validation_layers_for(
+        entry: &ash::Entry,
+        optional: bool,
+    ) -> Result<Box<[*const i8]>, RendererError> {
+        match Self::get_validation_layers(entry) {
+            Err(RendererError::UnavailableValidationLayers(available)) if optional => {
+                log::warn!(
+                    "Going on with {} of the {} validation layers",
+                    available.len(),
+                    crate::VK_VALIDATION_LAYERS.len()
+                );
+                Ok(available)
+            }
+            result => result,
+        }
+    }
+
     /// This function fetches the list of validation layers that we want to use.
     /// It also checks whether the validation layers we want are available. If
     /// we fail to query the driver for supported extensions, the driver will
@@ -544,7 +740,36 @@ impl Renderer {
         }
     }
 
-    /// Checks if a given physical device is capable of performing the
+    /// Picks the first device that can draw to images of `format`. Whether it
+    /// can present does not matter.
+    fn select_offscreen_physical_device(
+        instance: &ash::Instance,
+        format: vk::Format,
+    ) -> Result<vk::PhysicalDevice, RendererError> {
+        let devices = unsafe { instance.enumerate_physical_devices() }
+            .map_err(RendererError::VulkanInfoQueryFailed)?;
+
+        if devices.is_empty() {
+            return Err(RendererError::NoAvailableVideoAdapter);
+        }
+
+        devices
+            .iter()
+            .cloned()
+            .find(|&d| {
+                let family_indices = queue::QueueFamilyIndices::fetch_offscreen(instance, d);
+                let format_properties =
+                    unsafe { instance.get_physical_device_format_properties(d, format) };
+
+                family_indices.graphics_family.is_some()
+                    && format_properties
+                        .optimal_tiling_features
+                        .contains(vk::FormatFeatureFlags::COLOR_ATTACHMENT)
+            })
+            .ok_or(RendererError::NoSupportedVideoAdapter)
+    }
+
+    /// Checks if a given physical device is capable of performing the
     /// operations the application needs.
     fn check_physical_device_suitability(
         instance: &ash::Instance,
@@ -597,14 +822,18 @@ impl Renderer {
         instance: &ash::Instance,
         family_indices: &queue::QueueFamilyIndices,
         physical_device: vk::PhysicalDevice,
+        extensions: &[*const i8],
+        debugging_optional: bool,
     ) -> Result<ash::Device, RendererError> {
         let mut queue_create_infos = Vec::new();
         // Using a set, if there are any repeated queue indices, they will be
         // reduced to a single one.
-        let mut unique_queue_indices = std::collections::HashSet::from([
-            family_indices.graphics_family.unwrap(),
-            family_indices.present_family.unwrap(),
-        ]);
+        let mut unique_queue_indices =
+            std::collections::HashSet::from([family_indices.graphics_family.unwrap()]);
+        // An offscreen renderer presents nothing
+        if let Some(family) = family_indices.present_family {
+            unique_queue_indices.insert(family);
+        }
         if let Some(family) = family_indices.dedicated_transfer_family {
             unique_queue_indices.insert(family);
         }
@@ -625,7 +854,7 @@ impl Renderer {
         };
 
         let validation_layers = if crate::DEBUG_ENABLED {
-            Self::get_validation_layers(entry)?
+            Self::get_validation_layers_for(entry, debugging_optional)?
         } else {
             Box::new([])
         };
@@ -634,11 +863,8 @@ impl Renderer {
             p_queue_create_infos: queue_create_infos.as_ptr(),
             queue_create_info_count: queue_create_infos.len().try_into().unwrap(),
             p_enabled_features: &device_features as *const vk::PhysicalDeviceFeatures,
-            pp_enabled_extension_names: crate::VK_REQUIRED_DEVICE_EXTENSIONS.as_ptr(),
-            enabled_extension_count: crate::VK_REQUIRED_DEVICE_EXTENSIONS
-                .len()
-                .try_into()
-                .unwrap(),
+            pp_enabled_extension_names: extensions.as_ptr(),
+            enabled_extension_count: extensions.len().try_into().unwrap(),
             pp_enabled_layer_names: if validation_layers.len() > 0 {
                 validation_layers.as_ptr()
             } else {
@@ -655,9 +881,12 @@ impl Renderer {
             .map_err(RendererError::FailedToCreateDevice)
     }
 
+    /// Creates the render pass, leaving the images it renders to in
+    /// `final_layout`.
     fn create_render_pass(
         device: &ash::Device,
         img_format: vk::Format,
+        final_layout: vk::ImageLayout,
     ) -> Result<vk::RenderPass, RendererError> {
         // This structrue defines what we do with the images we receive to
         // render into.
@@ -676,8 +905,8 @@ impl Renderer {
 
             // We don't care about the format the image has when we get it
             initial_layout: vk::ImageLayout::UNDEFINED,
-            // But we want it to be ready for presenting when we render it
-            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            // But we want it to be ready for whatever comes next
+            final_layout,
 
             ..Default::default()
         };
@@ -918,10 +1147,14 @@ impl Renderer {
             .map_err(RendererError::FailedToCreateShaderModule)
     }
 
+    /// Records drawing a frame to `framebuffer`, followed by copying its
+    /// image to a buffer if `readback` is set.
     fn record_command_buffer(
         &mut self,
         cmdbuf: vk::CommandBuffer,
-        img_idx: usize,
+        framebuffer: vk::Framebuffer,
+        extent: vk::Extent2D,
+        readback: Option<ImageReadback>,
     ) -> Result<(), RendererError> {
         let command_buffer_begin_info = vk::CommandBufferBeginInfo {
             ..Default::default()
@@ -936,10 +1169,10 @@ impl Renderer {
 
         let render_pass_begin_info = vk::RenderPassBeginInfo {
             render_pass: self.vk_res.render_pass(),
-            framebuffer: self.vk_res.framebuffers()[img_idx],
+            framebuffer,
             render_area: vk::Rect2D {
                 offset: vk::Offset2D { x: 0, y: 0 },
-                extent: self.swapchain_img_extent,
+                extent,
             },
             clear_value_count: 1,
             p_clear_values: &vk::ClearValue {
@@ -953,15 +1186,15 @@ impl Renderer {
         let viewport = vk::Viewport {
             x: 0.0,
             y: 0.0,
-            width: self.swapchain_img_extent.width as f32,
-            height: self.swapchain_img_extent.height as f32,
+            width: extent.width as f32,
+            height: extent.height as f32,
             min_depth: 0.0,
             max_depth: 1.0,
         };
 
         let scissor = vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
-            extent: self.swapchain_img_extent,
+            extent,
         };
 
         unsafe {
@@ -975,30 +1208,37 @@ impl Renderer {
                 vk::PipelineBindPoint::GRAPHICS,
                 self.vk_res.pipeline(),
             );
-            let buffers = [self.test_vertex_buffer.buffer_handle];
-            self.vk_res.device().cmd_bind_vertex_buffers(
-                cmdbuf,
-                0,
-                &buffers,
-                &[self.test_vertex_buffer.offset],
-            );
-            self.vk_res.device().cmd_bind_index_buffer(
-                cmdbuf,
-                self.test_index_buffer.buffer_handle,
-                self.test_index_buffer.offset,
-                vk::IndexType::UINT16,
-            );
             self.vk_res
                 .device()
                 .cmd_set_viewport(cmdbuf, 0, &[viewport]);
             self.vk_res.device().cmd_set_scissor(cmdbuf, 0, &[scissor]);
-            self.vk_res
-                .device()
-                // .cmd_draw(cmdbuf, VERTICES.len().try_into().unwrap(), 1, 0, 0);
-                .cmd_draw_indexed(cmdbuf, 6, 1, 0, 0, 0);
+
+            for mesh in self.meshes.values() {
+                self.vk_res.device().cmd_bind_vertex_buffers(
+                    cmdbuf,
+                    0,
+                    &[mesh.vertex_buffer.buffer_handle],
+                    &[mesh.vertex_buffer.offset],
+                );
+                self.vk_res.device().cmd_bind_index_buffer(
+                    cmdbuf,
+                    mesh.index_buffer.buffer_handle,
+                    mesh.index_buffer.offset,
+                    vk::IndexType::UINT16,
+                );
+                self.vk_res
+                    .device()
+                    .cmd_draw_indexed(cmdbuf, mesh.index_count, 1, 0, 0, 0);
+            }
 
             self.vk_res.device().cmd_end_render_pass(cmdbuf);
+        }
+
+        if let Some(readback) = readback {
+            self.record_image_readback(cmdbuf, readback, extent);
+        }
 
+        unsafe {
             self.vk_res
                 .device()
                 .end_command_buffer(cmdbuf)
@@ -1006,8 +1246,528 @@ impl Renderer {
         }
     }
 
+    /// Uploads a mesh, drawn as a list of triangles every frame from then on,
+    /// until destroyed with [destroy_mesh](Renderer::destroy_mesh). Meshes
+    /// are drawn in the order they were created.
+    pub fn create_mesh(
+        &mut self,
+        vertices: &[Point2DColorRGBVertex],
+        indices: &[u16],
+    ) -> Result<MeshHandle, RendererError> {
+        let mesh = self.upload_mesh(vertices, indices)?;
+
+        let handle = MeshHandle::new(self.next_mesh_id);
+        self.next_mesh_id += 1;
+        self.meshes.insert(handle, mesh);
+
+        Ok(handle)
+    }
+
+    /// Replaces the vertices and indices of the mesh. This waits for the
+    /// frames in flight to finish.
+    pub fn update_mesh(
+        &mut self,
+        mesh: MeshHandle,
+        vertices: &[Point2DColorRGBVertex],
+        indices: &[u16],
+    ) -> Result<(), RendererError> {
+        if !self.meshes.contains_key(&mesh) {
+            return Err(RendererError::UnknownMesh);
+        }
+
+        // The old buffers are kept until the new ones are up, so that the
+        // mesh is left as it was if the upload fails
+        let new_mesh = self.upload_mesh(vertices, indices)?;
+
+        self.wait_device_idle("update_mesh");
+        let old_mesh = self.meshes.insert(mesh, new_mesh).unwrap();
+        self.free_mesh(old_mesh)
+    }
+
+    /// Destroys the mesh, which is not drawn anymore. This waits for the
+    /// frames in flight to finish.
+    pub fn destroy_mesh(&mut self, mesh: MeshHandle) -> Result<(), RendererError> {
+        if !self.meshes.contains_key(&mesh) {
+            return Err(RendererError::UnknownMesh);
+        }
+
+        self.wait_device_idle("destroy_mesh");
+        let mesh = self.meshes.remove(&mesh).unwrap();
+        self.free_mesh(mesh)
+    }
+
+    fn upload_mesh(
+        &mut self,
+        vertices: &[Point2DColorRGBVertex],
+        indices: &[u16],
+    ) -> Result<Mesh, RendererError> {
+        if vertices.is_empty() || indices.is_empty() {
+            return Err(RendererError::EmptyMesh);
+        }
+        if let Some(&index) = indices
+            .iter()
+            .find(|&&index| usize::from(index) >= vertices.len())
+        {
+            return Err(RendererError::MeshIndexOutOfRange {
+                index,
+                vertex_count: vertices.len(),
+            });
+        }
+        let index_count = u32::try_from(indices.len()).map_err(|_| RendererError::ObjectTooBig)?;
+
+        let vertex_data_len = vertices
+            .len()
+            .checked_mul(std::mem::size_of::<Point2DColorRGBVertex>())
+            .ok_or(RendererError::ObjectTooBig)?;
+        let vertex_data =
+            unsafe { std::slice::from_raw_parts(vertices.as_ptr() as *const u8, vertex_data_len) };
+
+        let vertex_buffer = unsafe { self.vk_res.create_vertex_vbuffer(vertex_data)? };
+        let index_buffer = match unsafe { self.vk_res.create_index_vbuffer(indices) } {
+            Ok(index_buffer) => index_buffer,
+            Err(e) => {
+                // Nothing uses it yet
+                unsafe { self.vk_res.free_vbuffer(vertex_buffer)? };
+                return Err(e);
+            }
+        };
+
+        Ok(Mesh {
+            vertex_buffer,
+            index_buffer,
+            index_count,
+        })
+    }
+
+    /// Frees the buffers of the mesh. The device must not be using them.
+    fn free_mesh(&mut self, mesh: Mesh) -> Result<(), RendererError> {
+        unsafe {
+            self.vk_res.free_vbuffer(mesh.vertex_buffer)?;
+            self.vk_res.free_vbuffer(mesh.index_buffer)
+        }
+    }
+
+    fn wait_device_idle(&self, caller: &str) {
+        unsafe { self.vk_res.device().device_wait_idle() }.unwrap_or_else(|e| {
+            log::error!("FATAL: Could not wait for device idle on {caller}: {e}");
+            std::process::abort();
+        });
+    }
+
+    /// Creates a surface and a swapchain for another window, so that frames
+    /// can be drawn to it with [draw_frame_for](Renderer::draw_frame_for). The
+    /// window shares the device, pipeline and buffers with every other window
+    /// the renderer draws to.
+    pub fn attach_window(
+        &mut self,
+        window: WindowInstance,
+        messenger: &WindowMessenger,
+    ) -> Result<(), RendererError> {
+        if self.vk_res.offscreen_target().is_some() {
+            return Err(RendererError::OffscreenRenderer);
+        }
+        if self.vk_res.target_index(window).is_some() {
+            return Err(RendererError::WindowAlreadyAttached);
+        }
+
+        let surface = Self::request_surface(&self.vk_res, window, messenger)?;
+        let target_idx = self.vk_res.add_target(window, surface);
+
+        // Whatever fails from here on, the target goes, along with its
+        // surface, so that attaching the window may be retried
+        Self::request_viewport_extent(window, messenger)
+            .and_then(|window_extent| self.init_target(target_idx, window_extent))
+            .inspect_err(|_| self.vk_res.destroy_target(target_idx))
+    }
+
+    fn init_target(
+        &mut self,
+        target_idx: usize,
+        window_extent: vk::Extent2D,
+    ) -> Result<(), RendererError> {
+        let surface = self.vk_res.target(target_idx).surface;
+        let swapchain_info = self.check_surface(surface)?;
+
+        self.vk_res
+            .create_swapchain(target_idx, &swapchain_info, window_extent)?;
+        self.vk_res.target_mut(target_idx).window_extent = window_extent;
+
+        unsafe {
+            self.vk_res
+                .create_frame_objects(target_idx, MAX_CONCURRENT_FRAMES)
+        }
+    }
+
+    /// Checks we can present to the surface with the device and render pass
+    /// we already have.
+    fn check_surface(
+        &self,
+        surface: vk::SurfaceKHR,
+    ) -> Result<swapchain_info::SwapchainSupportInfo, RendererError> {
+        // The device was picked for the main window, we can only hope it can
+        // present to this one as well.
+        let present_family = self.vk_res.queue_families().present_family.unwrap();
+        let present_supported = unsafe {
+            self.vk_res
+                .surface_loader()
+                .get_physical_device_surface_support(
+                    self.vk_res.physical_device(),
+                    present_family,
+                    surface,
+                )
+        }
+        .map_err(RendererError::VulkanInfoQueryFailed)?;
+        if !present_supported {
+            return Err(RendererError::UnsupportedSurface);
+        }
+
+        let swapchain_info = swapchain_info::SwapchainSupportInfo::fetch(
+            self.vk_res.surface_loader(),
+            surface,
+            self.vk_res.physical_device(),
+        )
+        .map_err(RendererError::VulkanInfoQueryFailed)?;
+
+        // The render pass is shared, so the format must match
+        if swapchain_info.select_format() != Some(self.swapchain_img_format) {
+            return Err(RendererError::IncompatibleSurfaceFormat);
+        }
+
+        Ok(swapchain_info)
+    }
+
+    /// Destroys the surface and swapchain of the window. Call it before the
+    /// window itself gets destroyed.
+    pub fn detach_window(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        let target_idx = self.target_index(window)?;
+
+        self.wait_device_idle("detach_window");
+
+        self.vk_res.destroy_target(target_idx);
+        if self.pending_capture == Some(window) {
+            self.pending_capture = None;
+        }
+
+        Ok(())
+    }
+
+    fn target_index(&self, window: WindowInstance) -> Result<usize, RendererError> {
+        self.vk_res
+            .target_index(window)
+            .ok_or(RendererError::UnknownWindow)
+    }
+
+    /// Draws a frame to the main window, or to the offscreen image.
     pub fn draw_frame(&mut self) -> Result<(), RendererError> {
-        let in_flight_fence = self.vk_res.in_flight_fences()[self.current_frame];
+        if self.vk_res.offscreen_target().is_some() {
+            self.draw_offscreen_frame()
+        } else {
+            self.draw_frame_for(self.main_window)
+        }
+    }
+
+    fn draw_offscreen_frame(&mut self) -> Result<(), RendererError> {
+        let target = self.vk_res.offscreen_target().unwrap();
+        let command_buffer = target.command_buffer;
+        let fence = target.fence;
+        let framebuffer = target.framebuffer;
+        let extent = target.extent;
+        let format = target.format;
+        let image = target.image;
+        let readback = target.readback;
+
+        unsafe {
+            self.vk_res
+                .device()
+                .wait_for_fences(&[fence], false, u64::MAX)
+                .map_err(RendererError::FailedToDrawFrame)?;
+
+            self.vk_res
+                .device()
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .map_err(RendererError::FailedToDrawFrame)?;
+        }
+
+        // The image has a readback buffer of its own
+        let capture = self.pending_capture.take().map(|_| readback);
+        self.record_command_buffer(
+            command_buffer,
+            framebuffer,
+            extent,
+            capture.map(|readback| ImageReadback {
+                image,
+                layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer: readback.buffer,
+            }),
+        )?;
+
+        self.submit_offscreen(command_buffer, fence)?;
+        self.vk_res.offscreen_target_mut().unwrap().has_frame = true;
+
+        if let Some(readback) = capture {
+            self.finish_capture(fence, readback, extent, format)?;
+        }
+
+        Ok(())
+    }
+
+    /// Waits for the frame being captured to be drawn, and keeps it for
+    /// [take_captured_frame](Renderer::take_captured_frame).
+    fn finish_capture(
+        &mut self,
+        fence: vk::Fence,
+        readback: ReadbackBuffer,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<(), RendererError> {
+        let size = vk::DeviceSize::from(extent.width) * vk::DeviceSize::from(extent.height) * 4;
+        let pixels = unsafe {
+            self.vk_res
+                .device()
+                .wait_for_fences(&[fence], false, u64::MAX)
+                .map_err(RendererError::FailedToDrawFrame)?;
+
+            self.vk_res.read_back(readback, size, format)?
+        };
+
+        self.captured_frame = Some(CapturedFrame {
+            width: extent.width,
+            height: extent.height,
+            pixels,
+        });
+
+        Ok(())
+    }
+
+    /// Copies the last frame drawn offscreen to the CPU, as tightly packed
+    /// RGBA8 rows, top to bottom. If no frame was drawn yet, one is drawn
+    /// first. This waits for the GPU to finish.
+    pub fn read_offscreen_pixels(&mut self) -> Result<Vec<u8>, RendererError> {
+        if !self
+            .vk_res
+            .offscreen_target()
+            .ok_or(RendererError::NotOffscreen)?
+            .has_frame
+        {
+            self.draw_offscreen_frame()?;
+        }
+
+        let target = self.vk_res.offscreen_target().unwrap();
+        let command_buffer = target.command_buffer;
+        let fence = target.fence;
+        let extent = target.extent;
+        let format = target.format;
+        let readback = target.readback;
+        let image_readback = ImageReadback {
+            image: target.image,
+            // Where the render pass left it
+            layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer: readback.buffer,
+        };
+
+        let device = self.vk_res.device();
+        unsafe {
+            device
+                .wait_for_fences(&[fence], false, u64::MAX)
+                .map_err(RendererError::FailedToDrawFrame)?;
+            device
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+                .map_err(RendererError::FailedToDrawFrame)?;
+            device
+                .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+                .map_err(RendererError::CommandBufferRecordingError)?;
+        }
+
+        self.record_image_readback(command_buffer, image_readback, extent);
+
+        unsafe {
+            self.vk_res
+                .device()
+                .end_command_buffer(command_buffer)
+                .map_err(RendererError::CommandBufferRecordingError)?;
+        }
+
+        self.submit_offscreen(command_buffer, fence)?;
+
+        unsafe {
+            self.vk_res
+                .device()
+                .wait_for_fences(&[fence], false, u64::MAX)
+                .map_err(RendererError::FailedToDrawFrame)?;
+
+            self.vk_res.read_back(readback, readback.size, format)
+        }
+    }
+
+    /// Captures the next frame drawn to the main window, or offscreen. Once
+    /// drawn, [take_captured_frame](Renderer::take_captured_frame) returns
+    /// it.
+    pub fn capture_next_frame(&mut self) {
+        self.pending_capture = Some(self.main_window);
+    }
+
+    /// Captures the next frame drawn to the window. Only one capture can be
+    /// pending, this replaces any other.
+    pub fn capture_next_frame_for(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        self.target_index(window)?;
+        self.pending_capture = Some(window);
+        Ok(())
+    }
+
+    /// Returns the last frame captured, if it wasn't taken yet.
+    pub fn take_captured_frame(&mut self) -> Option<CapturedFrame> {
+        self.captured_frame.take()
+    }
+
+    /// Records copying the image to the buffer, once the writes to it are
+    /// done. The image is left in the layout it was in.
+    fn record_image_readback(
+        &self,
+        cmdbuf: vk::CommandBuffer,
+        readback: ImageReadback,
+        extent: vk::Extent2D,
+    ) {
+        let device = self.vk_res.device();
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+
+        let to_transfer_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+            old_layout: readback.layout,
+            new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: readback.image,
+            subresource_range,
+            ..Default::default()
+        };
+
+        // Zeroed row length and height mean tightly packed
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+
+        let restore_barrier = vk::ImageMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_READ,
+            dst_access_mask: vk::AccessFlags::empty(),
+            old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            new_layout: readback.layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: readback.image,
+            subresource_range,
+            ..Default::default()
+        };
+        let buffer_barrier = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: readback.buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        };
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmdbuf,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_barrier],
+            );
+            device.cmd_copy_image_to_buffer(
+                cmdbuf,
+                readback.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                readback.buffer,
+                &[region],
+            );
+            device.cmd_pipeline_barrier(
+                cmdbuf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE | vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[buffer_barrier],
+                &[restore_barrier],
+            );
+        }
+    }
+
+    fn submit_offscreen(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        fence: vk::Fence,
+    ) -> Result<(), RendererError> {
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo {
+            command_buffer_count: 1,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            self.vk_res
+                .device()
+                .reset_fences(&[fence])
+                .map_err(RendererError::FailedToDrawFrame)?;
+
+            self.vk_res
+                .device()
+                .queue_submit(self.graphics_queue, &[submit_info], fence)
+                .map_err(RendererError::FailedToDrawFrame)
+        }
+    }
+
+    pub fn draw_frame_for(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        let target_idx = self.target_index(window)?;
+
+        // There is nothing to present to while the window is minimized, nor
+        // while the window is too small to have a swapchain.
+        if !self.vk_res.target(target_idx).is_drawable() {
+            return Ok(());
+        }
+
+        // The buffer is set up before acquiring an image, as there is no
+        // giving the image back if it fails
+        let capture = if self.pending_capture == Some(window) {
+            self.pending_capture = None;
+            Some(self.prepare_capture(target_idx)?)
+        } else {
+            None
+        };
+
+        let target = self.vk_res.target(target_idx);
+        let current_frame = target.current_frame;
+        let in_flight_fence = target.in_flight_fences[current_frame];
+        let img_available_semaphore = target.img_available_semaphores[current_frame];
+        let render_finished_semaphore = target.render_finished_semaphores[current_frame];
+        let command_buffer = target.command_buffers[current_frame];
+        let swapchain = target.swapchain;
 
         let img_idx = unsafe {
             self.vk_res
@@ -1021,9 +1781,9 @@ impl Renderer {
                 .swapchain_loader()
                 // Acquire a new image to render to
                 .acquire_next_image(
-                    self.vk_res.swapchain(),
+                    swapchain,
                     u64::MAX,
-                    self.vk_res.img_available_semaphores()[self.current_frame],
+                    img_available_semaphore,
                     vk::Fence::null(),
                 )
                 .map_err(RendererError::FailedToDrawFrame)?;
@@ -1031,25 +1791,28 @@ impl Renderer {
             self.vk_res
                 .device()
                 // Reset our command buffer in order to record our commands
-                .reset_command_buffer(
-                    self.command_buffers[self.current_frame],
-                    vk::CommandBufferResetFlags::empty(),
-                )
+                .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
                 .map_err(RendererError::FailedToDrawFrame)?;
 
             img_idx
         };
 
+        let target = self.vk_res.target(target_idx);
+        let framebuffer = target.framebuffers[usize::try_from(img_idx).unwrap()];
+        let extent = target.swapchain_img_extent;
+        let readback = capture.map(|capture_buffer| ImageReadback {
+            image: target.swapchain_images[usize::try_from(img_idx).unwrap()],
+            layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            buffer: capture_buffer.buffer,
+        });
+
         // We call the our function that will record the command buffer
-        self.record_command_buffer(
-            self.command_buffers[self.current_frame],
-            img_idx.try_into().unwrap(),
-        )?;
+        self.record_command_buffer(command_buffer, framebuffer, extent, readback)?;
 
-        let wait_semaphores = [self.vk_res.img_available_semaphores()[self.current_frame]];
+        let wait_semaphores = [img_available_semaphore];
         let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let signal_semaphores = [self.vk_res.render_finished_semaphores()[self.current_frame]];
-        let command_buffers = [self.command_buffers[self.current_frame]];
+        let signal_semaphores = [render_finished_semaphore];
+        let command_buffers = [command_buffer];
         let submit_info = vk::SubmitInfo {
             wait_semaphore_count: 1,
             p_wait_semaphores: wait_semaphores.as_ptr(),
@@ -1070,14 +1833,10 @@ impl Renderer {
             self.vk_res
                 .device()
                 // Submit our commands to the graphics queue
-                .queue_submit(
-                    self.graphics_queue,
-                    &[submit_info],
-                    self.vk_res.in_flight_fences()[self.current_frame],
-                )
+                .queue_submit(self.graphics_queue, &[submit_info], in_flight_fence)
                 .map_err(RendererError::FailedToDrawFrame)?;
 
-            let swapchains = [self.vk_res.swapchain()];
+            let swapchains = [swapchain];
 
             let present_info = vk::PresentInfoKHR {
                 wait_semaphore_count: 1,
@@ -1092,33 +1851,193 @@ impl Renderer {
                 .swapchain_loader()
                 .queue_present(self.present_queue, &present_info)
                 .map_err(RendererError::FailedToDrawFrame)?;
+        }
+
+        let target = self.vk_res.target_mut(target_idx);
+        target.current_frame = (current_frame + 1) % MAX_CONCURRENT_FRAMES;
 
-            self.current_frame = (self.current_frame + 1) % MAX_CONCURRENT_FRAMES;
+        if let Some(capture_buffer) = capture {
+            self.finish_capture(
+                in_flight_fence,
+                capture_buffer,
+                extent,
+                self.swapchain_img_format.format,
+            )?;
         }
 
         Ok(())
     }
 
-    pub fn window_resized(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
-        log::debug!("Renderer resize requested: {width}, {height}");
+    /// Checks frames of the target can be captured, and returns the buffer
+    /// to copy them to.
+    fn prepare_capture(&mut self, target_idx: usize) -> Result<ReadbackBuffer, RendererError> {
+        let target = self.vk_res.target(target_idx);
+        if !target.capturable {
+            return Err(RendererError::CaptureUnsupported);
+        }
+        let format = self.swapchain_img_format.format;
+        if !offscreen_target::is_rgba8_compatible(format) {
+            return Err(RendererError::UnsupportedCaptureFormat(format));
+        }
 
-        unsafe { self.vk_res.device().device_wait_idle() }.unwrap_or_else(|e| {
-            log::error!("FATAL: Could not wait for device idle on window_resized: {e}");
-            std::process::abort();
-        });
+        let extent = target.swapchain_img_extent;
+        let size = vk::DeviceSize::from(extent.width) * vk::DeviceSize::from(extent.height) * 4;
+        unsafe { self.vk_res.capture_buffer(size) }
+    }
+
+    /// Recreates the swapchain of the main window for its new size. The size
+    /// is in pixels, the `pixel_w` and `pixel_h` of a
+    /// [WindowResize](crate::WindowEvent::WindowResize), not the logical
+    /// window size.
+    pub fn window_resized(
+        &mut self,
+        pixel_width: u32,
+        pixel_height: u32,
+    ) -> Result<(), RendererError> {
+        self.window_resized_for(self.main_window, pixel_width, pixel_height)
+    }
+
+    pub fn window_resized_for(
+        &mut self,
+        window: WindowInstance,
+        pixel_width: u32,
+        pixel_height: u32,
+    ) -> Result<(), RendererError> {
+        log::debug!("Renderer resize requested: {pixel_width}, {pixel_height} pixels");
+
+        let target_idx = self.target_index(window)?;
+        let target = self.vk_res.target_mut(target_idx);
+        target.window_extent = vk::Extent2D {
+            width: pixel_width,
+            height: pixel_height,
+        };
+
+        if target.minimized || target.surface_lost {
+            // We'll get to it once the window is restored
+            target.swapchain_stale = true;
+            Ok(())
+        } else {
+            self.recreate_swapchain(target_idx, "window_resized")
+        }
+    }
+
+    /// Stops drawing frames to the main window until
+    /// [window_restored](Renderer::window_restored) is called.
+    /// [draw_frame](Renderer::draw_frame) does nothing meanwhile.
+    pub fn window_minimized(&mut self) {
+        // The main window is only detached by dropping the renderer
+        let _ = self.window_minimized_for(self.main_window);
+    }
+
+    pub fn window_minimized_for(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        log::debug!("Renderer paused, window minimized");
+        let target_idx = self.target_index(window)?;
+        self.vk_res.target_mut(target_idx).minimized = true;
+        Ok(())
+    }
+
+    pub fn window_restored(&mut self) -> Result<(), RendererError> {
+        self.window_restored_for(self.main_window)
+    }
+
+    pub fn window_restored_for(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        log::debug!("Renderer resumed, window restored");
+        let target_idx = self.target_index(window)?;
+        let target = self.vk_res.target_mut(target_idx);
+        target.minimized = false;
+
+        if target.swapchain_stale && !target.surface_lost {
+            self.recreate_swapchain(target_idx, "window_restored")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Destroys the surface and swapchain of the main window, keeping
+    /// everything else. Frames are not drawn to it until
+    /// [surface_recreated](Renderer::surface_recreated) is called.
+    pub fn surface_lost(&mut self) -> Result<(), RendererError> {
+        self.surface_lost_for(self.main_window)
+    }
+
+    pub fn surface_lost_for(&mut self, window: WindowInstance) -> Result<(), RendererError> {
+        log::debug!("Renderer paused, window surface lost");
+        let target_idx = self.target_index(window)?;
+        if self.vk_res.target(target_idx).surface_lost {
+            return Ok(());
+        }
+
+        self.wait_device_idle("surface_lost");
+
+        self.vk_res.destroy_target_surface(target_idx);
+        self.vk_res.target_mut(target_idx).surface_lost = true;
+
+        Ok(())
+    }
+
+    /// Asks the host for a new surface for the main window, and creates its
+    /// swapchain. The device, pipelines and buffers are kept. If it fails,
+    /// calling it again retries.
+    pub fn surface_recreated(&mut self, messenger: &WindowMessenger) -> Result<(), RendererError> {
+        self.surface_recreated_for(self.main_window, messenger)
+    }
+
+    pub fn surface_recreated_for(
+        &mut self,
+        window: WindowInstance,
+        messenger: &WindowMessenger,
+    ) -> Result<(), RendererError> {
+        log::debug!("Renderer resumed, window surface recreated");
+        let target_idx = self.target_index(window)?;
+        let target = self.vk_res.target(target_idx);
+        // A stale swapchain means an earlier call got the surface, but failed
+        // before making a swapchain for it, so we try again from there
+        if !target.surface_lost && !target.swapchain_stale {
+            return Ok(());
+        }
+
+        if target.surface_lost {
+            let surface = Self::request_surface(&self.vk_res, window, messenger)?;
+            let target = self.vk_res.target_mut(target_idx);
+            target.surface = surface;
+            target.surface_lost = false;
+            // There is no swapchain until we get to create it
+            target.swapchain_stale = true;
+        }
+
+        // The surface is ours from here on, even if it turns out unusable
+        self.check_surface(self.vk_res.target(target_idx).surface)?;
+
+        let window_extent = Self::request_viewport_extent(window, messenger)?;
+        self.vk_res.target_mut(target_idx).window_extent = window_extent;
+        self.recreate_swapchain(target_idx, "surface_recreated")
+    }
+
+    fn recreate_swapchain(&mut self, target_idx: usize, caller: &str) -> Result<(), RendererError> {
+        self.wait_device_idle(caller);
 
         let swapchain_info = swapchain_info::SwapchainSupportInfo::fetch(
             self.vk_res.surface_loader(),
-            self.vk_res.surface(),
+            self.vk_res.target(target_idx).surface,
             self.vk_res.physical_device(),
         )
         .map_err(RendererError::VulkanInfoQueryFailed)?;
 
-        self.vk_res.destroy_swapchain();
+        // A swapchain can't have a zero-sized extent. Some drivers report it
+        // as the current extent of a minimized window.
+        let window_extent = self.vk_res.target(target_idx).window_extent;
+        let swapchain_img_extent = swapchain_info.select_extent(window_extent);
+        if swapchain_img_extent.width == 0 || swapchain_img_extent.height == 0 {
+            log::debug!("Zero-sized window, postponing swapchain recreation");
+            self.vk_res.target_mut(target_idx).swapchain_stale = true;
+            return Ok(());
+        }
+
+        self.vk_res.destroy_swapchain(target_idx);
         self.vk_res
-            .create_swapchain(&swapchain_info, vk::Extent2D { width, height })?;
+            .create_swapchain(target_idx, &swapchain_info, window_extent)?;
 
-        self.swapchain_img_extent = swapchain_info.select_extent(vk::Extent2D { width, height });
+        self.vk_res.target_mut(target_idx).swapchain_stale = false;
 
         Ok(())
     }
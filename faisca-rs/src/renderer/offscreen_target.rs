@@ -0,0 +1,94 @@
+use super::capture::ReadbackBuffer;
+use ash::vk;
+
+/// The image a renderer without a window draws to, and the buffer it is read
+/// back through. The
+/// [RendererResourceKeeper](super::resources::RendererResourceKeeper) owns it,
+/// along with everything the windowed targets share.
+pub struct OffscreenTarget {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+
+    pub image: vk::Image,
+    pub image_memory: vk::DeviceMemory,
+    pub image_view: vk::ImageView,
+    pub framebuffer: vk::Framebuffer,
+
+    /// Holds a copy of the image after a read back.
+    pub readback: ReadbackBuffer,
+
+    pub command_buffer: vk::CommandBuffer,
+    /// Signaled once the GPU is done with the command buffer.
+    pub fence: vk::Fence,
+    /// Whether a frame was drawn to the image. Until then, the image holds
+    /// nothing to read.
+    pub has_frame: bool,
+}
+
+impl OffscreenTarget {
+    pub fn new(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            format,
+            extent,
+
+            image: vk::Image::null(),
+            image_memory: vk::DeviceMemory::null(),
+            image_view: vk::ImageView::null(),
+            framebuffer: vk::Framebuffer::null(),
+
+            readback: ReadbackBuffer::default(),
+
+            command_buffer: vk::CommandBuffer::null(),
+            fence: vk::Fence::null(),
+            has_frame: false,
+        }
+    }
+
+    /// The size of the image in bytes, once read back.
+    #[inline]
+    pub fn readback_size(&self) -> vk::DeviceSize {
+        vk::DeviceSize::from(self.extent.width) * vk::DeviceSize::from(self.extent.height) * 4
+    }
+}
+
+/// Whether images in `format` can be read back as RGBA8: it must have four
+/// 8 bit channels, in RGBA or BGRA order.
+pub fn is_rgba8_compatible(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+    )
+}
+
+/// Turns the pixels of an image in `format`, which must be
+/// [RGBA8 compatible](is_rgba8_compatible), into RGBA8 in place.
+pub fn convert_to_rgba8(format: vk::Format, pixels: &mut [u8]) {
+    if matches!(
+        format,
+        vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB
+    ) {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba8_conversion_test() {
+        let mut pixels = [1, 2, 3, 4, 5, 6, 7, 8];
+        convert_to_rgba8(vk::Format::R8G8B8A8_UNORM, &mut pixels);
+        assert_eq!(pixels, [1, 2, 3, 4, 5, 6, 7, 8]);
+        convert_to_rgba8(vk::Format::B8G8R8A8_SRGB, &mut pixels);
+        assert_eq!(pixels, [3, 2, 1, 4, 7, 6, 5, 8]);
+
+        assert!(is_rgba8_compatible(vk::Format::B8G8R8A8_UNORM));
+        assert!(!is_rgba8_compatible(vk::Format::R16G16B16A16_SFLOAT));
+    }
+}
@@ -34,14 +34,7 @@ impl QueueFamilyIndices {
             queue_family_indices.present_family = Some(family);
         }
 
-        queue_family_indices.dedicated_transfer_family = properties
-            .iter()
-            .enumerate()
-            .find(|(_, prop)| {
-                prop.queue_flags.contains(vk::QueueFlags::TRANSFER) &&
-                (!prop.queue_flags.contains(vk::QueueFlags::GRAPHICS))
-            })
-            .map(|(i, _)| i.try_into().unwrap());
+        queue_family_indices.dedicated_transfer_family = Self::find_dedicated_transfer(&properties);
 
         if !queue_family_indices.has_all() {
             for (i, prop) in properties.iter().enumerate() {
@@ -65,6 +58,33 @@ impl QueueFamilyIndices {
         queue_family_indices
     }
 
+    /// Like [fetch](QueueFamilyIndices::fetch), for a renderer that draws
+    /// offscreen: no present family is looked for, and it stays `None`.
+    pub fn fetch_offscreen(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        QueueFamilyIndices {
+            graphics_family: properties
+                .iter()
+                .position(|prop| prop.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+                .map(|i| i.try_into().unwrap()),
+            present_family: None,
+            dedicated_transfer_family: Self::find_dedicated_transfer(&properties),
+        }
+    }
+
+    fn find_dedicated_transfer(properties: &[vk::QueueFamilyProperties]) -> Option<u32> {
+        properties
+            .iter()
+            .enumerate()
+            .find(|(_, prop)| {
+                prop.queue_flags.contains(vk::QueueFlags::TRANSFER) &&
+                (!prop.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            })
+            .map(|(i, _)| i.try_into().unwrap())
+    }
+
     pub fn has_all(&self) -> bool {
         self.graphics_family.is_some() && self.present_family.is_some()
     }
@@ -4,11 +4,15 @@ use crate::{
     ffi,
     renderer::{
         buffer::{BufferManager, VirtualBuffer},
+        capture::ReadbackBuffer,
+        offscreen_target::{self, OffscreenTarget},
         queue::QueueFamilyIndices,
+        surface_target::SurfaceTarget,
         swapchain_info::SwapchainSupportInfo,
         RendererError,
     },
     util::OnDropDefer,
+    WindowInstance,
 };
 use ash::{
     extensions::{ext, khr},
@@ -24,7 +28,6 @@ pub struct RendererResourceKeeper {
     debug_messenger: vk::DebugUtilsMessengerEXT,
 
     surface_loader: Option<khr::Surface>,
-    surface: vk::SurfaceKHR,
 
     physical_device: vk::PhysicalDevice,
 
@@ -33,9 +36,13 @@ pub struct RendererResourceKeeper {
     device: Option<ash::Device>,
 
     swapchain_loader: Option<khr::Swapchain>,
-    swapchain: vk::SwapchainKHR,
-    swapchain_image_views: Vec<vk::ImageView>,
-    swapchain_info: Option<SwapchainSupportInfo>,
+
+    /// One target per window we present to. The first one is the main window.
+    targets: Vec<SurfaceTarget>,
+    /// What we draw to instead, when there is no window at all.
+    offscreen_target: Option<OffscreenTarget>,
+    /// Where captured frames of the windows are copied to.
+    capture_buffer: Option<ReadbackBuffer>,
 
     render_pass: vk::RenderPass,
 
@@ -44,15 +51,9 @@ pub struct RendererResourceKeeper {
     pipeline_layout: vk::PipelineLayout,
     pipeline: vk::Pipeline,
 
-    framebuffers: Vec<vk::Framebuffer>,
-
     command_pool: vk::CommandPool,
     dedicated_transfer_command_pool: vk::CommandPool,
 
-    img_available_semaphores: Vec<vk::Semaphore>,
-    render_finished_semaphores: Vec<vk::Semaphore>,
-    in_flight_fences: Vec<vk::Fence>,
-
     buffer_manager: RefCell<BufferManager>,
     staging_buf: Option<VirtualBuffer>,
 }
@@ -102,16 +103,6 @@ impl RendererResourceKeeper {
         &mut self.surface_loader
     }
 
-    #[inline]
-    pub fn surface(&self) -> vk::SurfaceKHR {
-        self.surface
-    }
-
-    #[inline]
-    pub unsafe fn surface_mut(&mut self) -> &mut vk::SurfaceKHR {
-        &mut self.surface
-    }
-
     #[inline]
     pub fn physical_device(&self) -> vk::PhysicalDevice {
         self.physical_device
@@ -153,33 +144,280 @@ impl RendererResourceKeeper {
     }
 
     #[inline]
-    pub fn swapchain(&self) -> vk::SwapchainKHR {
-        self.swapchain
+    pub fn targets(&self) -> &[SurfaceTarget] {
+        self.targets.as_slice()
     }
 
     #[inline]
-    pub unsafe fn swapchain_mut(&mut self) -> &mut vk::SwapchainKHR {
-        &mut self.swapchain
+    pub fn target(&self, idx: usize) -> &SurfaceTarget {
+        &self.targets[idx]
     }
 
     #[inline]
-    pub fn swapchain_info(&self) -> &SwapchainSupportInfo {
-        self.swapchain_info.as_ref().unwrap()
+    pub fn target_mut(&mut self, idx: usize) -> &mut SurfaceTarget {
+        &mut self.targets[idx]
     }
 
+    /// Returns the index of the target presenting to `window`, if any.
     #[inline]
-    pub fn swapchain_info_mut(&mut self) -> &mut SwapchainSupportInfo {
-        self.swapchain_info.as_mut().unwrap()
+    pub fn target_index(&self, window: WindowInstance) -> Option<usize> {
+        self.targets.iter().position(|t| t.window == window)
+    }
+
+    /// Takes ownership of `surface`, returning the index of its new target.
+    /// The target has no swapchain nor frame objects yet.
+    pub fn add_target(&mut self, window: WindowInstance, surface: vk::SurfaceKHR) -> usize {
+        self.targets.push(SurfaceTarget::new(window, surface));
+        self.targets.len() - 1
     }
 
     #[inline]
-    pub fn swapchain_image_views(&self) -> &[vk::ImageView] {
-        self.swapchain_image_views.as_slice()
+    pub fn offscreen_target(&self) -> Option<&OffscreenTarget> {
+        self.offscreen_target.as_ref()
     }
 
     #[inline]
-    pub unsafe fn swapchain_image_views_mut(&mut self) -> &mut Vec<vk::ImageView> {
-        &mut self.swapchain_image_views
+    pub fn offscreen_target_mut(&mut self) -> Option<&mut OffscreenTarget> {
+        self.offscreen_target.as_mut()
+    }
+
+    /// Creates the image to draw to without a window, along with its
+    /// framebuffer, readback buffer and frame objects. The render pass must
+    /// exist already.
+    pub unsafe fn create_offscreen_target(
+        &mut self,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<(), RendererError> {
+        assert!(self.offscreen_target.is_none());
+        // Stored right away, so that whatever gets created is destroyed along
+        // with the keeper if something fails halfway
+        self.offscreen_target = Some(OffscreenTarget::new(format, extent));
+
+        let image_info = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        };
+        let image = self
+            .device()
+            .create_image(&image_info, None)
+            .map_err(RendererError::FailedToCreateImage)?;
+        self.offscreen_target.as_mut().unwrap().image = image;
+
+        let image_memory = self.allocate_memory(
+            self.device().get_image_memory_requirements(image),
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        self.offscreen_target.as_mut().unwrap().image_memory = image_memory;
+        self.device()
+            .bind_image_memory(image, image_memory, 0)
+            .map_err(RendererError::FailedToCreateImage)?;
+
+        let img_view_info = vk::ImageViewCreateInfo {
+            image,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let image_view = self
+            .device()
+            .create_image_view(&img_view_info, None)
+            .map_err(RendererError::FailedToCreateImageView)?;
+        self.offscreen_target.as_mut().unwrap().image_view = image_view;
+
+        let framebuffer_info = vk::FramebufferCreateInfo {
+            render_pass: self.render_pass(),
+            attachment_count: 1,
+            p_attachments: &image_view as *const _,
+            width: extent.width,
+            height: extent.height,
+            layers: 1,
+            ..Default::default()
+        };
+        let framebuffer = self
+            .device()
+            .create_framebuffer(&framebuffer_info, None)
+            .map_err(RendererError::FailedToCreateFramebuffer)?;
+        self.offscreen_target.as_mut().unwrap().framebuffer = framebuffer;
+
+        let readback_size = self.offscreen_target.as_ref().unwrap().readback_size();
+        self.offscreen_target.as_mut().unwrap().readback =
+            self.create_readback_buffer(readback_size)?;
+
+        let command_buffer_info = vk::CommandBufferAllocateInfo {
+            command_pool: self.command_pool(),
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+            ..Default::default()
+        };
+        let command_buffer = self
+            .device()
+            .allocate_command_buffers(&command_buffer_info)
+            .map_err(RendererError::FailedToCreateCommandBuffer)?[0];
+        self.offscreen_target.as_mut().unwrap().command_buffer = command_buffer;
+
+        let fence_info = vk::FenceCreateInfo {
+            flags: vk::FenceCreateFlags::SIGNALED,
+            ..Default::default()
+        };
+        let fence = self
+            .device()
+            .create_fence(&fence_info, None)
+            .map_err(RendererError::FailedToCreateSyncObject)?;
+        self.offscreen_target.as_mut().unwrap().fence = fence;
+
+        Ok(())
+    }
+
+    /// Destroys the offscreen target, if any. The device must not be using
+    /// it.
+    fn destroy_offscreen_target(&mut self) {
+        let (Some(target), Some(device)) = (self.offscreen_target.take(), &self.device) else {
+            return;
+        };
+
+        log::debug!("Destroying Vulkan offscreen target");
+        unsafe {
+            device.destroy_fence(target.fence, None);
+            if target.command_buffer != vk::CommandBuffer::null() {
+                device.free_command_buffers(self.command_pool, &[target.command_buffer]);
+            }
+            device.destroy_buffer(target.readback.buffer, None);
+            device.free_memory(target.readback.memory, None);
+            device.destroy_framebuffer(target.framebuffer, None);
+            device.destroy_image_view(target.image_view, None);
+            device.destroy_image(target.image, None);
+            device.free_memory(target.image_memory, None);
+        }
+    }
+
+    /// Creates a host visible buffer of `size` bytes to copy images to.
+    pub unsafe fn create_readback_buffer(
+        &self,
+        size: vk::DeviceSize,
+    ) -> Result<ReadbackBuffer, RendererError> {
+        let buffer_info = vk::BufferCreateInfo {
+            size,
+            usage: vk::BufferUsageFlags::TRANSFER_DST,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let buffer = OnDropDefer::new(
+            self.device()
+                .create_buffer(&buffer_info, None)
+                .map_err(RendererError::FailedToCreateBuffer)?,
+            |buffer| self.device().destroy_buffer(buffer, None),
+        );
+
+        let memory = OnDropDefer::new(
+            self.allocate_memory(
+                self.device()
+                    .get_buffer_memory_requirements(*buffer.as_ref()),
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?,
+            |memory| self.device().free_memory(memory, None),
+        );
+        self.device()
+            .bind_buffer_memory(*buffer.as_ref(), *memory.as_ref(), 0)
+            .map_err(RendererError::FailedToCreateBuffer)?;
+
+        Ok(ReadbackBuffer {
+            buffer: buffer.take(),
+            memory: memory.take(),
+            size,
+        })
+    }
+
+    /// Returns the buffer frames get captured to, creating it, or recreating
+    /// it if smaller than `size`. It is kept for the next captures.
+    pub unsafe fn capture_buffer(
+        &mut self,
+        size: vk::DeviceSize,
+    ) -> Result<ReadbackBuffer, RendererError> {
+        match self.capture_buffer {
+            Some(capture_buffer) if capture_buffer.size >= size => return Ok(capture_buffer),
+            Some(capture_buffer) => {
+                self.device().destroy_buffer(capture_buffer.buffer, None);
+                self.device().free_memory(capture_buffer.memory, None);
+                self.capture_buffer = None;
+            }
+            None => (),
+        }
+
+        let capture_buffer = self.create_readback_buffer(size)?;
+        self.capture_buffer = Some(capture_buffer);
+        Ok(capture_buffer)
+    }
+
+    /// Copies `size` bytes of an image in `format` out of the buffer, as
+    /// RGBA8. The GPU must be done copying the image to it.
+    pub unsafe fn read_back(
+        &self,
+        readback: ReadbackBuffer,
+        size: vk::DeviceSize,
+        format: vk::Format,
+    ) -> Result<Vec<u8>, RendererError> {
+        let mut pixels = vec![0u8; usize::try_from(size).unwrap()];
+
+        // The memory is host coherent, no flushing needed
+        let mapped = self
+            .device()
+            .map_memory(readback.memory, 0, size, vk::MemoryMapFlags::empty())
+            .map_err(RendererError::FailedToMapBufferMemory)?;
+        std::ptr::copy_nonoverlapping(mapped as *const u8, pixels.as_mut_ptr(), pixels.len());
+        self.device().unmap_memory(readback.memory);
+
+        offscreen_target::convert_to_rgba8(format, &mut pixels);
+
+        Ok(pixels)
+    }
+
+    unsafe fn allocate_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        memory_property_flags: vk::MemoryPropertyFlags,
+    ) -> Result<vk::DeviceMemory, RendererError> {
+        let memory_properties = self
+            .instance()
+            .get_physical_device_memory_properties(self.physical_device());
+        let memory_type_index = BufferManager::find_memory_type(
+            memory_properties,
+            requirements.memory_type_bits,
+            memory_property_flags,
+        )
+        .ok_or(RendererError::UnavailableMemoryType {
+            memory_type_flags: requirements.memory_type_bits,
+            memory_property_flags,
+        })?;
+
+        let alloc_info = vk::MemoryAllocateInfo {
+            allocation_size: requirements.size,
+            memory_type_index,
+            ..Default::default()
+        };
+        self.device()
+            .allocate_memory(&alloc_info, None)
+            .map_err(RendererError::MemAllocError)
     }
 
     #[inline]
@@ -222,16 +460,6 @@ impl RendererResourceKeeper {
         &mut self.pipeline
     }
 
-    #[inline]
-    pub fn framebuffers(&self) -> &[vk::Framebuffer] {
-        self.framebuffers.as_slice()
-    }
-
-    #[inline]
-    pub unsafe fn framebuffers_mut(&mut self) -> &mut Vec<vk::Framebuffer> {
-        &mut self.framebuffers
-    }
-
     #[inline]
     pub fn command_pool(&self) -> vk::CommandPool {
         self.command_pool
@@ -252,40 +480,28 @@ impl RendererResourceKeeper {
         &mut self.dedicated_transfer_command_pool
     }
 
-    #[inline]
-    pub fn img_available_semaphores(&self) -> &[vk::Semaphore] {
-        &self.img_available_semaphores
-    }
-
-    #[inline]
-    pub unsafe fn img_available_semaphores_mut(&mut self) -> &mut Vec<vk::Semaphore> {
-        &mut self.img_available_semaphores
-    }
-
-    #[inline]
-    pub fn render_finished_semaphores(&self) -> &[vk::Semaphore] {
-        &self.render_finished_semaphores
-    }
-
-    #[inline]
-    pub unsafe fn render_finished_semaphores_mut(&mut self) -> &mut Vec<vk::Semaphore> {
-        &mut self.render_finished_semaphores
-    }
-
-    #[inline]
-    pub fn in_flight_fences(&self) -> &[vk::Fence] {
-        &self.in_flight_fences
-    }
-
-    #[inline]
-    pub unsafe fn in_flight_fences_mut(&mut self) -> &mut Vec<vk::Fence> {
-        &mut self.in_flight_fences
-    }
+    /// Creates the sync objects and command buffers for `count` concurrent
+    /// frames of the target.
+    pub unsafe fn create_frame_objects(
+        &mut self,
+        target_idx: usize,
+        count: usize,
+    ) -> Result<(), RendererError> {
+        let command_buffer_info = vk::CommandBufferAllocateInfo {
+            command_pool: self.command_pool(),
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: count.try_into().unwrap(),
+            ..Default::default()
+        };
+        self.targets[target_idx].command_buffers = self
+            .device()
+            .allocate_command_buffers(&command_buffer_info)
+            .map_err(RendererError::FailedToCreateCommandBuffer)?;
 
-    pub unsafe fn create_sync_objects(&mut self, count: usize) -> Result<(), RendererError> {
-        self.img_available_semaphores.reserve(count);
-        self.render_finished_semaphores.reserve(count);
-        self.in_flight_fences.reserve(count);
+        let target = &mut self.targets[target_idx];
+        target.img_available_semaphores.reserve(count);
+        target.render_finished_semaphores.reserve(count);
+        target.in_flight_fences.reserve(count);
 
         let semaphore_info = vk::SemaphoreCreateInfo::default();
         let fence_info = vk::FenceCreateInfo {
@@ -294,21 +510,29 @@ impl RendererResourceKeeper {
         };
 
         for i in 0..count {
-            self.img_available_semaphores.push(
-                self.device()
-                    .create_semaphore(&semaphore_info, None)
-                    .map_err(RendererError::FailedToCreateSyncObject)?,
-            );
-            self.render_finished_semaphores.push(
-                self.device()
-                    .create_semaphore(&semaphore_info, None)
-                    .map_err(RendererError::FailedToCreateSyncObject)?,
-            );
-            self.in_flight_fences.push(
-                self.device()
-                    .create_fence(&fence_info, None)
-                    .map_err(RendererError::FailedToCreateSyncObject)?,
-            );
+            let img_available_semaphore = self
+                .device()
+                .create_semaphore(&semaphore_info, None)
+                .map_err(RendererError::FailedToCreateSyncObject)?;
+            self.targets[target_idx]
+                .img_available_semaphores
+                .push(img_available_semaphore);
+
+            let render_finished_semaphore = self
+                .device()
+                .create_semaphore(&semaphore_info, None)
+                .map_err(RendererError::FailedToCreateSyncObject)?;
+            self.targets[target_idx]
+                .render_finished_semaphores
+                .push(render_finished_semaphore);
+
+            let in_flight_fence = self
+                .device()
+                .create_fence(&fence_info, None)
+                .map_err(RendererError::FailedToCreateSyncObject)?;
+            self.targets[target_idx]
+                .in_flight_fences
+                .push(in_flight_fence);
         }
 
         Ok(())
@@ -316,6 +540,7 @@ impl RendererResourceKeeper {
 
     pub fn create_swapchain(
         &mut self,
+        target_idx: usize,
         swapchain_info: &SwapchainSupportInfo,
         window_extent: vk::Extent2D,
     ) -> Result<(), RendererError> {
@@ -338,14 +563,25 @@ impl RendererResourceKeeper {
                 0
             };
 
+        // Frames can only be captured if the images can be copied from
+        let capturable = swapchain_info
+            .capabilities
+            .supported_usage_flags
+            .contains(vk::ImageUsageFlags::TRANSFER_SRC);
+        let image_usage = if capturable {
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC
+        } else {
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+        };
+
         let mut swapchain_create_info = vk::SwapchainCreateInfoKHR {
-            surface: self.surface(),
+            surface: self.targets[target_idx].surface,
             min_image_count: image_count,
             image_format: surface_format.format,
             image_color_space: surface_format.color_space,
             image_extent: selected_extent,
             image_array_layers: 1,
-            image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            image_usage,
             pre_transform: swapchain_info.capabilities.current_transform,
             composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
             present_mode,
@@ -368,33 +604,39 @@ impl RendererResourceKeeper {
             swapchain_create_info.image_sharing_mode = vk::SharingMode::EXCLUSIVE;
         }
 
-        self.swapchain = unsafe {
+        let swapchain = unsafe {
             self.swapchain_loader()
                 .create_swapchain(&swapchain_create_info, None)
                 .map_err(RendererError::FailedToCreateSwapchain)?
         };
 
-        self.swapchain_info = Some(swapchain_info.clone());
+        let target = &mut self.targets[target_idx];
+        target.swapchain = swapchain;
+        target.swapchain_info = Some(swapchain_info.clone());
+        target.swapchain_img_extent = selected_extent;
+        target.capturable = capturable;
 
         log::debug!("Creating image views");
-        self.create_image_views(surface_format)?;
+        self.create_image_views(target_idx, surface_format)?;
         log::debug!("Creating framebuffers");
-        self.create_framebuffers(selected_extent)?;
+        self.create_framebuffers(target_idx, selected_extent)?;
 
         Ok(())
     }
 
     fn create_image_views(
         &mut self,
+        target_idx: usize,
         swapchain_img_format: vk::SurfaceFormatKHR,
     ) -> Result<(), RendererError> {
         let images = unsafe {
             self.swapchain_loader()
-                .get_swapchain_images(self.swapchain())
+                .get_swapchain_images(self.targets[target_idx].swapchain)
                 .map_err(RendererError::FailedToCreateImageView)?
         };
 
-        assert!(self.swapchain_image_views.is_empty());
+        assert!(self.targets[target_idx].swapchain_image_views.is_empty());
+        self.targets[target_idx].swapchain_images = images.clone();
         for image in images {
             let img_view_info = vk::ImageViewCreateInfo {
                 image,
@@ -419,15 +661,24 @@ impl RendererResourceKeeper {
             let image_view = unsafe { self.device().create_image_view(&img_view_info, None) }
                 .map_err(RendererError::FailedToCreateImageView)?;
 
-            self.swapchain_image_views.push(image_view);
+            self.targets[target_idx]
+                .swapchain_image_views
+                .push(image_view);
         }
 
         Ok(())
     }
 
-    fn create_framebuffers(&mut self, extent: vk::Extent2D) -> Result<(), RendererError> {
-        assert!(self.framebuffers.is_empty());
-        for image_view in &self.swapchain_image_views {
+    fn create_framebuffers(
+        &mut self,
+        target_idx: usize,
+        extent: vk::Extent2D,
+    ) -> Result<(), RendererError> {
+        let target = &self.targets[target_idx];
+        assert!(target.framebuffers.is_empty());
+
+        let mut framebuffers = Vec::with_capacity(target.swapchain_image_views.len());
+        for image_view in &target.swapchain_image_views {
             let framebuffer_info = vk::FramebufferCreateInfo {
                 render_pass: self.render_pass(),
                 attachment_count: 1,
@@ -441,28 +692,89 @@ impl RendererResourceKeeper {
             let framebuffer = unsafe { self.device().create_framebuffer(&framebuffer_info, None) }
                 .map_err(RendererError::FailedToCreateFramebuffer)?;
 
-            self.framebuffers.push(framebuffer);
+            framebuffers.push(framebuffer);
         }
+        self.targets[target_idx].framebuffers = framebuffers;
 
         Ok(())
     }
 
-    pub fn destroy_swapchain(&mut self) {
+    pub fn destroy_swapchain(&mut self, target_idx: usize) {
+        Self::destroy_target_swapchain(
+            self.device.as_ref().unwrap(),
+            self.swapchain_loader.as_ref(),
+            &mut self.targets[target_idx],
+        );
+    }
+
+    fn destroy_target_swapchain(
+        device: &ash::Device,
+        swapchain_loader: Option<&khr::Swapchain>,
+        target: &mut SurfaceTarget,
+    ) {
         log::debug!("Destroying Vulkan framebuffers");
-        for &fbuf in self.framebuffers.iter() {
-            unsafe { self.device().destroy_framebuffer(fbuf, None) };
+        for &fbuf in target.framebuffers.iter() {
+            unsafe { device.destroy_framebuffer(fbuf, None) };
         }
-        self.framebuffers.clear();
+        target.framebuffers.clear();
 
         log::debug!("Destroying Vulkan image views");
-        for &view in self.swapchain_image_views.iter() {
-            unsafe { self.device().destroy_image_view(view, None) };
+        for &view in target.swapchain_image_views.iter() {
+            unsafe { device.destroy_image_view(view, None) };
         }
-        self.swapchain_image_views.clear();
+        target.swapchain_image_views.clear();
+        target.swapchain_images.clear();
 
-        if let Some(swapchain_loader) = &self.swapchain_loader {
+        if let Some(swapchain_loader) = swapchain_loader {
             log::debug!("Destroying Vulkan swapchain");
-            unsafe { swapchain_loader.destroy_swapchain(self.swapchain, None) };
+            unsafe { swapchain_loader.destroy_swapchain(target.swapchain, None) };
+            target.swapchain = vk::SwapchainKHR::null();
+        }
+    }
+
+    /// Destroys the swapchain and surface of the target, leaving it with a
+    /// null surface. The frame objects are kept.
+    pub fn destroy_target_surface(&mut self, target_idx: usize) {
+        self.destroy_swapchain(target_idx);
+
+        let target = &mut self.targets[target_idx];
+        if let Some(surface_loader) = &self.surface_loader {
+            log::debug!("Destroying Vulkan surface");
+            unsafe { surface_loader.destroy_surface(target.surface, None) };
+        }
+        target.surface = vk::SurfaceKHR::null();
+    }
+
+    /// Destroys the target and everything it owns, surface included. The
+    /// device must not be using any of its resources.
+    pub fn destroy_target(&mut self, target_idx: usize) {
+        let mut target = self.targets.remove(target_idx);
+
+        if let Some(device) = &self.device {
+            if !target.command_buffers.is_empty() {
+                unsafe { device.free_command_buffers(self.command_pool, &target.command_buffers) };
+            }
+
+            log::debug!("Destroying Vulkan semaphores");
+            for &sem in target
+                .img_available_semaphores
+                .iter()
+                .chain(target.render_finished_semaphores.iter())
+            {
+                unsafe { device.destroy_semaphore(sem, None) };
+            }
+
+            log::debug!("Destroying Vulkan fences");
+            for &fence in target.in_flight_fences.iter() {
+                unsafe { device.destroy_fence(fence, None) };
+            }
+
+            Self::destroy_target_swapchain(device, self.swapchain_loader.as_ref(), &mut target);
+        }
+
+        if let Some(surface_loader) = &self.surface_loader {
+            log::debug!("Destroying Vulkan surface");
+            unsafe { surface_loader.destroy_surface(target.surface, None) };
         }
     }
 
@@ -553,6 +865,11 @@ impl RendererResourceKeeper {
         Ok(vbuffer)
     }
 
+    /// Gives the space of the buffer back. The device must not be using it.
+    pub unsafe fn free_vbuffer(&self, vbuffer: VirtualBuffer) -> Result<(), RendererError> {
+        self.buffer_manager.borrow_mut().free_vbuffer(vbuffer)
+    }
+
     pub unsafe fn update_vbuffer(
         &self,
         vbuffer: &mut VirtualBuffer,
@@ -650,7 +967,6 @@ impl Default for RendererResourceKeeper {
             debug_messenger: vk::DebugUtilsMessengerEXT::null(),
 
             surface_loader: None,
-            surface: vk::SurfaceKHR::null(),
 
             physical_device: vk::PhysicalDevice::null(),
             queue_families: QueueFamilyIndices::none(),
@@ -658,9 +974,10 @@ impl Default for RendererResourceKeeper {
             device: None,
 
             swapchain_loader: None,
-            swapchain: vk::SwapchainKHR::null(),
-            swapchain_image_views: Vec::new(),
-            swapchain_info: None,
+
+            targets: Vec::new(),
+            offscreen_target: None,
+            capture_buffer: None,
 
             render_pass: vk::RenderPass::null(),
 
@@ -669,15 +986,9 @@ impl Default for RendererResourceKeeper {
             pipeline_layout: vk::PipelineLayout::null(),
             pipeline: vk::Pipeline::null(),
 
-            framebuffers: Vec::new(),
-
             command_pool: vk::CommandPool::null(),
             dedicated_transfer_command_pool: vk::CommandPool::null(),
 
-            img_available_semaphores: Vec::new(),
-            render_finished_semaphores: Vec::new(),
-            in_flight_fences: Vec::new(),
-
             buffer_manager: RefCell::new(BufferManager::new()),
             staging_buf: None,
         }
@@ -692,18 +1003,17 @@ impl Drop for RendererResourceKeeper {
                 std::process::abort();
             });
 
-            log::debug!("Destroying Vulkan semaphores");
-            for &sem in self
-                .img_available_semaphores
-                .iter()
-                .chain(self.render_finished_semaphores.iter())
-            {
-                unsafe { self.device().destroy_semaphore(sem, None) };
+            while !self.targets.is_empty() {
+                self.destroy_target(self.targets.len() - 1);
             }
-
-            log::debug!("Destroying Vulkan fences");
-            for &fence in self.in_flight_fences.iter() {
-                unsafe { self.device().destroy_fence(fence, None) };
+            self.destroy_offscreen_target();
+
+            if let Some(capture_buffer) = self.capture_buffer.take() {
+                log::debug!("Destroying Vulkan capture buffer");
+                unsafe {
+                    self.device().destroy_buffer(capture_buffer.buffer, None);
+                    self.device().free_memory(capture_buffer.memory, None);
+                }
             }
 
             log::debug!("Destroying Vulkan command pool");
@@ -729,8 +1039,6 @@ impl Drop for RendererResourceKeeper {
             log::debug!("Destroying Vulkan render pass");
             unsafe { self.device().destroy_render_pass(self.render_pass, None) };
 
-            self.destroy_swapchain();
-
             log::debug!("Destroying Vulkan buffers");
             let mut buffer_manager = self.buffer_manager.take();
             unsafe { buffer_manager.destroy(self.device_mut().as_mut().unwrap()) };
@@ -739,9 +1047,9 @@ impl Drop for RendererResourceKeeper {
             unsafe { self.device().destroy_device(None) };
         }
 
-        if let Some(surface_loader) = &self.surface_loader {
-            log::debug!("Destroying Vulkan surface");
-            unsafe { surface_loader.destroy_surface(self.surface, None) };
+        // Without a device, the targets hold nothing but their surfaces
+        while !self.targets.is_empty() {
+            self.destroy_target(self.targets.len() - 1);
         }
 
         if let Some(debug_loader) = &self.debug_loader {
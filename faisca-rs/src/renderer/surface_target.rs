@@ -0,0 +1,74 @@
+use crate::{renderer::swapchain_info::SwapchainSupportInfo, WindowInstance};
+use ash::vk;
+
+/// Everything needed to present to a single window: its surface, its
+/// swapchain and the per-frame objects. The instance, device, pipelines and
+/// buffers are shared between all targets, the
+/// [RendererResourceKeeper](super::resources::RendererResourceKeeper) owns
+/// both.
+pub struct SurfaceTarget {
+    pub window: WindowInstance,
+    pub surface: vk::SurfaceKHR,
+
+    pub swapchain: vk::SwapchainKHR,
+    pub swapchain_images: Vec<vk::Image>,
+    pub swapchain_image_views: Vec<vk::ImageView>,
+    pub swapchain_info: Option<SwapchainSupportInfo>,
+    pub swapchain_img_extent: vk::Extent2D,
+    /// Whether the swapchain images can be copied from, which frame captures
+    /// need.
+    pub capturable: bool,
+    pub framebuffers: Vec<vk::Framebuffer>,
+
+    pub command_buffers: Vec<vk::CommandBuffer>,
+    pub img_available_semaphores: Vec<vk::Semaphore>,
+    pub render_finished_semaphores: Vec<vk::Semaphore>,
+    pub in_flight_fences: Vec<vk::Fence>,
+    pub current_frame: usize,
+
+    /// The last extent the window reported, which may differ from the
+    /// swapchain extent while the swapchain is stale.
+    pub window_extent: vk::Extent2D,
+    pub minimized: bool,
+    /// Set when the swapchain could not be recreated for the current window
+    /// extent (a zero-sized window, for instance). No frames are drawn until
+    /// it gets recreated.
+    pub swapchain_stale: bool,
+    /// Set while the window has no surface, as happens on Android while the
+    /// app is in the background.
+    pub surface_lost: bool,
+}
+
+impl SurfaceTarget {
+    pub fn new(window: WindowInstance, surface: vk::SurfaceKHR) -> Self {
+        Self {
+            window,
+            surface,
+
+            swapchain: vk::SwapchainKHR::null(),
+            swapchain_images: Vec::new(),
+            swapchain_image_views: Vec::new(),
+            swapchain_info: None,
+            swapchain_img_extent: vk::Extent2D::default(),
+            capturable: false,
+            framebuffers: Vec::new(),
+
+            command_buffers: Vec::new(),
+            img_available_semaphores: Vec::new(),
+            render_finished_semaphores: Vec::new(),
+            in_flight_fences: Vec::new(),
+            current_frame: 0,
+
+            window_extent: vk::Extent2D::default(),
+            minimized: false,
+            swapchain_stale: false,
+            surface_lost: false,
+        }
+    }
+
+    /// Whether frames can be drawn to this target right now.
+    #[inline]
+    pub fn is_drawable(&self) -> bool {
+        !self.minimized && !self.swapchain_stale && !self.surface_lost
+    }
+}
@@ -1,8 +1,10 @@
 use ash::vk;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(transparent)]
 pub struct Vector2(pub [f32; 2]);
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(transparent)]
 pub struct Vector3(pub [f32; 3]);
 
@@ -116,6 +118,9 @@ impl VertexLayout {
     }
 }
 
+/// The vertex the meshes are made of: a position in normalized device
+/// coordinates, and a color.
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub struct Point2DColorRGBVertex {
     pub point: Vector2,
@@ -0,0 +1,585 @@
+use crate::{EventSubscriber, SafeCString, WChanMsg, WindowEvent, WindowInstance, WindowMessenger};
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    time::Instant,
+};
+
+const LOG_MAGIC: &[u8; 8] = b"FSCAREC\0";
+/// Bumped whenever the way records are written changes.
+const LOG_VERSION: u32 = 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReplayError {
+    #[error("Failed to read or write the event log: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not an event log, or written by an incompatible version")]
+    BadHeader,
+    #[error("Unknown event type on the event log: {0}")]
+    UnknownEvent(u32),
+    #[error("Dropped file path on the event log is not valid")]
+    InvalidPath,
+}
+
+/// Where an app gets its window events from. Lets the same app loop run on a
+/// live [WindowMessenger], an [EventRecorder] or a [ReplayMessenger].
+pub trait EventSource {
+    fn try_recv(&mut self) -> Option<WChanMsg>;
+
+    /// Tells the source a frame was drawn. Recorded events are tagged with
+    /// the frame they were received on, and replayed on that same frame.
+    fn next_frame(&mut self) {}
+}
+
+impl EventSource for WindowMessenger {
+    fn try_recv(&mut self) -> Option<WChanMsg> {
+        WindowMessenger::try_recv(self)
+    }
+}
+
+impl EventSource for EventSubscriber {
+    fn try_recv(&mut self) -> Option<WChanMsg> {
+        EventSubscriber::try_recv(self)
+    }
+}
+
+/// A single event on the log.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventRecord {
+    /// Microseconds since the recording started.
+    pub timestamp_us: u64,
+    pub frame: u64,
+    pub window: WindowInstance,
+    pub event: WindowEvent,
+}
+
+/// Wraps a [WindowMessenger], writing every event received through it to a
+/// log that a [ReplayMessenger] can play back.
+///
+/// Window instances are logged as their raw values, which are only meaningful
+/// to the host the events were recorded from.
+pub struct EventRecorder<W: Write> {
+    messenger: WindowMessenger,
+    writer: W,
+    start: Instant,
+    frame: u64,
+    /// The first write error. Recording stops once one happens, and it is
+    /// reported by [finish](EventRecorder::finish).
+    error: Option<io::Error>,
+}
+
+impl<W: Write> EventRecorder<W> {
+    pub fn new(messenger: WindowMessenger, mut writer: W) -> Result<Self, ReplayError> {
+        writer.write_all(LOG_MAGIC)?;
+        writer.write_all(&LOG_VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            messenger,
+            writer,
+            start: Instant::now(),
+            frame: 0,
+            error: None,
+        })
+    }
+
+    /// The wrapped messenger, to send messages to the host with.
+    #[inline]
+    pub fn messenger(&self) -> &WindowMessenger {
+        &self.messenger
+    }
+
+    /// Flushes the log, returning the writer, or the first error that
+    /// happened while recording.
+    pub fn finish(mut self) -> Result<W, ReplayError> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn record(&mut self, w: WindowInstance, event: &WindowEvent) {
+        if self.error.is_some() {
+            return;
+        }
+
+        let record = EventRecord {
+            timestamp_us: self.start.elapsed().as_micros() as u64,
+            frame: self.frame,
+            window: w,
+            event: event.clone(),
+        };
+        if let Err(e) = write_record(&mut self.writer, &record) {
+            log::error!("Failed to record event, recording stopped: {e}");
+            self.error = Some(e);
+        }
+    }
+}
+
+impl<W: Write> EventSource for EventRecorder<W> {
+    fn try_recv(&mut self) -> Option<WChanMsg> {
+        let (w, event) = self.messenger.try_recv()?;
+        self.record(w, &event);
+        Some((w, event))
+    }
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+}
+
+/// Plays back a log written by an [EventRecorder]. Events are handed out on
+/// the frame they were recorded on, regardless of the time it takes to draw
+/// each frame, so that a replay is deterministic.
+///
+/// There is no host behind it, so an app that sends requests still needs a
+/// [WindowMessenger] to send them through.
+pub struct ReplayMessenger {
+    records: VecDeque<EventRecord>,
+    frame: u64,
+}
+
+impl ReplayMessenger {
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, ReplayError> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != LOG_MAGIC || read_u32(&mut reader)? != LOG_VERSION {
+            return Err(ReplayError::BadHeader);
+        }
+
+        let mut records = VecDeque::new();
+        loop {
+            match read_record(&mut reader) {
+                Ok(Some(record)) => records.push_back(record),
+                Ok(None) => break,
+                // The app likely died while recording, everything before the
+                // last record is still good to replay
+                Err(ReplayError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    log::warn!("The event log ends partway through a record, ignoring it");
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Self { records, frame: 0 })
+    }
+
+    /// Whether every event was already handed out.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// The events not handed out yet.
+    #[inline]
+    pub fn remaining(&self) -> impl Iterator<Item = &EventRecord> {
+        self.records.iter()
+    }
+}
+
+impl EventSource for ReplayMessenger {
+    fn try_recv(&mut self) -> Option<WChanMsg> {
+        if self.records.front()?.frame > self.frame {
+            return None;
+        }
+        self.records
+            .pop_front()
+            .map(|record| (record.window, record.event))
+    }
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+}
+
+fn event_tag(event: &WindowEvent) -> u32 {
+    // The enum is `repr(C, u32)`, so it starts with its discriminant
+    unsafe { *(event as *const WindowEvent as *const u32) }
+}
+
+fn write_record(writer: &mut impl Write, record: &EventRecord) -> io::Result<()> {
+    writer.write_all(&record.timestamp_us.to_le_bytes())?;
+    writer.write_all(&record.frame.to_le_bytes())?;
+    writer.write_all(&(record.window.as_raw() as u64).to_le_bytes())?;
+    writer.write_all(&event_tag(&record.event).to_le_bytes())?;
+
+    match &record.event {
+        WindowEvent::Quit
+        | WindowEvent::FocusGained
+        | WindowEvent::FocusLost
+        | WindowEvent::Minimized
+        | WindowEvent::Restored
+        | WindowEvent::Exposed
+        | WindowEvent::CloseRequested
+        | WindowEvent::SurfaceLost
+        | WindowEvent::SurfaceRecreated
+        | WindowEvent::AppWillEnterBackground
+        | WindowEvent::AppDidEnterForeground
+        | WindowEvent::DropBegin
+        | WindowEvent::DropComplete
+        | WindowEvent::ReloadRequested => Ok(()),
+        WindowEvent::WindowResize {
+            w,
+            h,
+            pixel_w,
+            pixel_h,
+        } => write_all(writer, &[w, h, pixel_w, pixel_h].map(|v| v.to_le_bytes())),
+        WindowEvent::Moved { x, y } => write_all(writer, &[x, y].map(|v| v.to_le_bytes())),
+        WindowEvent::ScaleFactorChanged { scale } => writer.write_all(&scale.to_le_bytes()),
+        WindowEvent::DropFile { path } => {
+            let path = path.as_cstr().to_bytes();
+            writer.write_all(&(path.len() as u32).to_le_bytes())?;
+            writer.write_all(path)
+        }
+        WindowEvent::KeyDown {
+            scancode,
+            keycode,
+            modifiers,
+            repeat,
+        }
+        | WindowEvent::KeyUp {
+            scancode,
+            keycode,
+            modifiers,
+            repeat,
+        } => {
+            writer.write_all(&scancode.to_le_bytes())?;
+            writer.write_all(&keycode.to_le_bytes())?;
+            writer.write_all(&modifiers.to_le_bytes())?;
+            writer.write_all(&[*repeat as u8])
+        }
+        WindowEvent::MouseMotion { x, y, xrel, yrel } => {
+            write_all(writer, &[x, y, xrel, yrel].map(|v| v.to_le_bytes()))
+        }
+        WindowEvent::MouseButton {
+            button,
+            pressed,
+            clicks,
+            x,
+            y,
+        } => {
+            writer.write_all(&[*button, *pressed as u8, *clicks])?;
+            write_all(writer, &[x, y].map(|v| v.to_le_bytes()))
+        }
+        WindowEvent::MouseWheel { x, y } => write_all(writer, &[x, y].map(|v| v.to_le_bytes())),
+        WindowEvent::ControllerAdded { id } | WindowEvent::ControllerRemoved { id } => {
+            writer.write_all(&id.to_le_bytes())
+        }
+        WindowEvent::ControllerButton {
+            id,
+            button,
+            pressed,
+        } => {
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&[*button, *pressed as u8])
+        }
+        WindowEvent::ControllerAxis { id, axis, value } => {
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&[*axis])?;
+            writer.write_all(&value.to_le_bytes())
+        }
+    }
+}
+
+fn write_all<const N: usize>(writer: &mut impl Write, values: &[[u8; N]]) -> io::Result<()> {
+    values.iter().try_for_each(|value| writer.write_all(value))
+}
+
+/// Returns `None` if the log ends before the record starts, and an
+/// [UnexpectedEof](io::ErrorKind::UnexpectedEof) error if it ends partway
+/// through it.
+fn read_record(reader: &mut impl Read) -> Result<Option<EventRecord>, ReplayError> {
+    let mut timestamp_us = [0; 8];
+    let mut read = 0;
+    while read < timestamp_us.len() {
+        match reader.read(&mut timestamp_us[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let timestamp_us = u64::from_le_bytes(timestamp_us);
+    let frame = read_u64(reader)?;
+    let window = WindowInstance::from_raw(read_u64(reader)? as usize);
+
+    let event = match read_u32(reader)? {
+        1 => WindowEvent::Quit,
+        2 => WindowEvent::WindowResize {
+            w: read_u32(reader)?,
+            h: read_u32(reader)?,
+            pixel_w: read_u32(reader)?,
+            pixel_h: read_u32(reader)?,
+        },
+        3 => WindowEvent::FocusGained,
+        4 => WindowEvent::FocusLost,
+        5 => WindowEvent::Minimized,
+        6 => WindowEvent::Restored,
+        7 => WindowEvent::Moved {
+            x: read_i32(reader)?,
+            y: read_i32(reader)?,
+        },
+        8 => WindowEvent::Exposed,
+        9 => WindowEvent::CloseRequested,
+        10 => WindowEvent::SurfaceLost,
+        11 => WindowEvent::SurfaceRecreated,
+        12 => WindowEvent::AppWillEnterBackground,
+        13 => WindowEvent::AppDidEnterForeground,
+        14 => WindowEvent::ScaleFactorChanged {
+            scale: f32::from_bits(read_u32(reader)?),
+        },
+        15 => WindowEvent::DropBegin,
+        16 => {
+            // Not trusting the length to allocate up front, a broken log may
+            // claim gigabytes
+            let len = read_u32(reader)? as usize;
+            let mut path = Vec::new();
+            reader.by_ref().take(len as u64).read_to_end(&mut path)?;
+            if path.len() < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            let path = String::from_utf8(path).map_err(|_| ReplayError::InvalidPath)?;
+            WindowEvent::DropFile {
+                path: SafeCString::allocate_from_str(path).map_err(|_| ReplayError::InvalidPath)?,
+            }
+        }
+        17 => WindowEvent::DropComplete,
+        18 => WindowEvent::ReloadRequested,
+        tag @ (1025 | 1026) => {
+            let scancode = read_u32(reader)?;
+            let keycode = read_i32(reader)?;
+            let modifiers = read_u16(reader)?;
+            let repeat = read_u8(reader)? != 0;
+            if tag == 1025 {
+                WindowEvent::KeyDown {
+                    scancode,
+                    keycode,
+                    modifiers,
+                    repeat,
+                }
+            } else {
+                WindowEvent::KeyUp {
+                    scancode,
+                    keycode,
+                    modifiers,
+                    repeat,
+                }
+            }
+        }
+        1027 => WindowEvent::MouseMotion {
+            x: read_i32(reader)?,
+            y: read_i32(reader)?,
+            xrel: read_i32(reader)?,
+            yrel: read_i32(reader)?,
+        },
+        1028 => WindowEvent::MouseButton {
+            button: read_u8(reader)?,
+            pressed: read_u8(reader)? != 0,
+            clicks: read_u8(reader)?,
+            x: read_i32(reader)?,
+            y: read_i32(reader)?,
+        },
+        1029 => WindowEvent::MouseWheel {
+            x: f32::from_bits(read_u32(reader)?),
+            y: f32::from_bits(read_u32(reader)?),
+        },
+        1030 => WindowEvent::ControllerAdded {
+            id: read_i32(reader)?,
+        },
+        1031 => WindowEvent::ControllerRemoved {
+            id: read_i32(reader)?,
+        },
+        1032 => WindowEvent::ControllerButton {
+            id: read_i32(reader)?,
+            button: read_u8(reader)?,
+            pressed: read_u8(reader)? != 0,
+        },
+        1033 => WindowEvent::ControllerAxis {
+            id: read_i32(reader)?,
+            axis: read_u8(reader)?,
+            value: read_u16(reader)? as i16,
+        },
+        tag => return Err(ReplayError::UnknownEvent(tag)),
+    };
+
+    Ok(Some(EventRecord {
+        timestamp_us,
+        frame,
+        window,
+        event,
+    }))
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut impl Read) -> io::Result<i32> {
+    read_u32(reader).map(|value| value as i32)
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{event_hub, AppMessage};
+
+    unsafe extern "C" fn recording_host(_w: WindowInstance, _msg: *const AppMessage) -> u32 {
+        1
+    }
+
+    unsafe extern "C" fn truncated_recording_host(
+        _w: WindowInstance,
+        _msg: *const AppMessage,
+    ) -> u32 {
+        1
+    }
+
+    /// Records the events through an [EventRecorder], as if the host sent
+    /// them on the given frames.
+    fn record_log(host: crate::MessageWindowFn, events: &[(u64, WindowEvent)]) -> Vec<u8> {
+        let w = WindowInstance::from_raw(1);
+        let messenger = unsafe { WindowMessenger::from_raw(host) };
+        let hub = event_hub::hub_for_host(host);
+        let mut recorder = EventRecorder::new(messenger, Vec::new()).unwrap();
+
+        let mut events = events.iter().peekable();
+        for frame in 0.. {
+            while let Some((_, event)) = events.next_if(|(event_frame, _)| *event_frame == frame) {
+                assert!(event_hub::publish(hub, w, event));
+                assert_eq!(recorder.try_recv(), Some((w, event.clone())));
+            }
+            if events.peek().is_none() {
+                break;
+            }
+            recorder.next_frame();
+        }
+        recorder.finish().unwrap()
+    }
+
+    #[test]
+    fn replay_test() {
+        let w = WindowInstance::from_raw(1);
+        let events = [
+            (0, WindowEvent::FocusGained),
+            (
+                0,
+                WindowEvent::WindowResize {
+                    w: 640,
+                    h: 480,
+                    pixel_w: 1280,
+                    pixel_h: 960,
+                },
+            ),
+            (
+                2,
+                WindowEvent::KeyDown {
+                    scancode: 4,
+                    keycode: 97,
+                    modifiers: 1,
+                    repeat: false,
+                },
+            ),
+            (
+                2,
+                WindowEvent::DropFile {
+                    path: SafeCString::allocate_from_str("texture.png").unwrap(),
+                },
+            ),
+            (
+                3,
+                WindowEvent::ControllerAxis {
+                    id: 0,
+                    axis: 1,
+                    value: -32768,
+                },
+            ),
+            (3, WindowEvent::MouseWheel { x: 0.5, y: -1.0 }),
+            (4, WindowEvent::Quit),
+        ];
+
+        let log = record_log(recording_host, &events);
+
+        let mut replay = ReplayMessenger::from_reader(log.as_slice()).unwrap();
+        let mut replayed = Vec::new();
+        for frame in 0..5 {
+            while let Some((replayed_w, event)) = replay.try_recv() {
+                assert_eq!(replayed_w, w);
+                replayed.push((frame, event));
+            }
+            replay.next_frame();
+        }
+        assert!(replay.is_finished());
+        assert_eq!(replayed, events);
+
+        assert!(matches!(
+            ReplayMessenger::from_reader(&b"not a log at all"[..]),
+            Err(ReplayError::BadHeader)
+        ));
+    }
+
+    #[test]
+    fn truncated_replay_test() {
+        let w = WindowInstance::from_raw(1);
+        let events = [
+            (0, WindowEvent::FocusGained),
+            (1, WindowEvent::MouseWheel { x: 0.5, y: -1.0 }),
+            (1, WindowEvent::Quit),
+        ];
+        let log = record_log(truncated_recording_host, &events);
+
+        // Cut in the middle of the last record, and of its timestamp
+        for cut in [3, 25] {
+            let mut replay = ReplayMessenger::from_reader(&log[..log.len() - cut]).unwrap();
+            assert_eq!(
+                replay
+                    .remaining()
+                    .map(|record| record.frame)
+                    .collect::<Vec<_>>(),
+                [0, 1]
+            );
+            assert_eq!(replay.try_recv(), Some((w, WindowEvent::FocusGained)));
+            replay.next_frame();
+            assert_eq!(
+                replay.try_recv(),
+                Some((w, WindowEvent::MouseWheel { x: 0.5, y: -1.0 }))
+            );
+            assert_eq!(replay.try_recv(), None);
+            assert!(replay.is_finished());
+        }
+
+        // A dropped file path claiming far more bytes than the log has
+        let drop = WindowEvent::DropFile {
+            path: SafeCString::allocate_from_str("texture.png").unwrap(),
+        };
+        let mut log = record_log(truncated_recording_host, &[(0, drop)]);
+        // After the header, and the timestamp, frame, window and tag
+        let len_at = LOG_MAGIC.len() + 4 + 28;
+        log[len_at..len_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ReplayMessenger::from_reader(log.as_slice())
+            .unwrap()
+            .is_finished());
+    }
+}
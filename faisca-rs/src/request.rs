@@ -0,0 +1,361 @@
+use crate::{
+    ffi::{DisplayBounds, DisplayMode, Extent2D, RawDisplayList, ResponseBinding, RESPONSE_OK},
+    AppMessage, WindowInstance, WindowMessenger,
+};
+use ash::vk::{self, Handle};
+use std::{
+    ffi::{c_void, CStr},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseError {
+    #[error("The host failed to handle the request, status code: {0}")]
+    HostError(i32),
+    #[error("The host did not accept the request")]
+    NotDelivered,
+    #[error("The host reported success, but sent no data")]
+    MissingData,
+    #[error("Timed out waiting for the host to answer")]
+    Timeout,
+    #[error("The response was already taken")]
+    Consumed,
+}
+
+/// A message the host answers to. See [WindowMessenger::request].
+pub trait Request {
+    type Output: FromResponse;
+
+    /// Builds the message to be sent, carrying the binding the host must
+    /// answer to.
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage;
+}
+
+/// A type that can be copied out of the data a host answers with.
+pub trait FromResponse: Sized + Send + 'static {
+    /// # Safety
+    /// `data` must be a valid pointer to the C representation of the type.
+    unsafe fn from_response(data: *const c_void) -> Self;
+}
+
+impl FromResponse for Extent2D {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        *(data as *const Extent2D)
+    }
+}
+
+impl FromResponse for WindowInstance {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        *(data as *const WindowInstance)
+    }
+}
+
+impl FromResponse for String {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        CStr::from_ptr(data as *const i8)
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl FromResponse for f32 {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        *(data as *const f32)
+    }
+}
+
+impl FromResponse for Vec<DisplayInfo> {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        let list = &*(data as *const RawDisplayList);
+        raw_slice(list.displays, list.count)
+            .iter()
+            .map(|raw| DisplayInfo {
+                name: if raw.name.is_null() {
+                    String::new()
+                } else {
+                    CStr::from_ptr(raw.name).to_string_lossy().into_owned()
+                },
+                bounds: raw.bounds,
+                current_mode: raw.current_mode,
+                modes: raw_slice(raw.modes, raw.mode_count).to_vec(),
+            })
+            .collect()
+    }
+}
+
+/// Hosts may send a null pointer along with a zero count, which
+/// `slice::from_raw_parts` does not accept.
+pub(crate) unsafe fn raw_slice<'a, T>(data: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+impl FromResponse for vk::SurfaceKHR {
+    unsafe fn from_response(data: *const c_void) -> Self {
+        vk::SurfaceKHR::from_raw(*(data as *const u64))
+    }
+}
+
+/// Asks the host for a Vulkan surface for the window.
+pub struct CreateVulkanSurface {
+    pub instance: vk::Instance,
+}
+
+impl Request for CreateVulkanSurface {
+    type Output = vk::SurfaceKHR;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::CreateVulkanSurface {
+            instance: self.instance.as_raw(),
+            binding,
+        }
+    }
+}
+
+/// Asks the host for the window size in pixels.
+pub struct QueryViewportExtents;
+
+impl Request for QueryViewportExtents {
+    type Output = Extent2D;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::QueryViewportExtents { binding }
+    }
+}
+
+/// Asks the host for a new window.
+pub struct CreateWindow {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Request for CreateWindow {
+    type Output = WindowInstance;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::CreateWindow {
+            width: self.width,
+            height: self.height,
+            binding,
+        }
+    }
+}
+
+/// Asks the host for the text on the system clipboard.
+pub struct QueryClipboardText;
+
+impl Request for QueryClipboardText {
+    type Output = String;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::QueryClipboardText { binding }
+    }
+}
+
+/// Asks the host for the scale factor of the window.
+pub struct QueryDisplayScale;
+
+impl Request for QueryDisplayScale {
+    type Output = f32;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::QueryDisplayScale { binding }
+    }
+}
+
+/// A display, as answered to [QueryDisplays].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayInfo {
+    pub name: String,
+    pub bounds: DisplayBounds,
+    pub current_mode: DisplayMode,
+    /// The modes the display can be switched to with
+    /// [Fullscreen::Real](crate::Fullscreen::Real).
+    pub modes: Vec<DisplayMode>,
+}
+
+/// Asks the host for the displays connected to the system. The index of a
+/// display in the answer is the one [Fullscreen::Real](crate::Fullscreen::Real)
+/// takes.
+pub struct QueryDisplays;
+
+impl Request for QueryDisplays {
+    type Output = Vec<DisplayInfo>;
+
+    fn into_message(self, binding: *const ResponseBinding) -> AppMessage {
+        AppMessage::QueryDisplays { binding }
+    }
+}
+
+type ResponseResult<T> = Result<T, ResponseError>;
+
+// The binding must come first, as the host hands its address back to us, and
+// we need to find the slot from it.
+#[repr(C)]
+struct ResponseSlot<T> {
+    binding: ResponseBinding,
+    result: Mutex<Option<ResponseResult<T>>>,
+    ready: Condvar,
+}
+
+impl<T> ResponseSlot<T> {
+    fn fulfill(&self, result: ResponseResult<T>) {
+        let mut result_guard = self.result.lock().unwrap();
+        *result_guard = Some(result);
+        self.ready.notify_all();
+    }
+}
+
+unsafe extern "C" fn complete_slot<T: FromResponse>(
+    binding: *const ResponseBinding,
+    status: i32,
+    data: *const c_void,
+) {
+    // This takes back the reference the host was holding
+    let slot = Arc::from_raw(binding as *const ResponseSlot<T>);
+
+    let result = if status != RESPONSE_OK {
+        Err(ResponseError::HostError(status))
+    } else if data.is_null() {
+        Err(ResponseError::MissingData)
+    } else {
+        Ok(T::from_response(data))
+    };
+    slot.fulfill(result);
+}
+
+/// The answer to a [Request], which may not have arrived yet.
+///
+/// Dropping a response before the host answers is fine, the host still has
+/// somewhere to write the answer to. The memory is only leaked if the host
+/// never answers.
+pub struct Response<T> {
+    slot: Arc<ResponseSlot<T>>,
+    taken: bool,
+}
+
+impl<T: FromResponse> Response<T> {
+    pub(crate) fn send<R>(messenger: &WindowMessenger, w: WindowInstance, request: R) -> Self
+    where
+        R: Request<Output = T>,
+    {
+        let slot = Arc::new(ResponseSlot {
+            binding: ResponseBinding::new(complete_slot::<T>),
+            result: Mutex::new(None),
+            ready: Condvar::new(),
+        });
+
+        // The host holds a reference until it answers
+        let binding = Arc::into_raw(Arc::clone(&slot)) as *const ResponseBinding;
+        if !messenger.send(w, &request.into_message(binding)) {
+            // The host won't answer, so the reference is ours to drop
+            drop(unsafe { Arc::from_raw(binding as *const ResponseSlot<T>) });
+            slot.fulfill(Err(ResponseError::NotDelivered));
+        }
+
+        Self { slot, taken: false }
+    }
+}
+
+impl<T> Response<T> {
+    /// Returns the answer if it already arrived, without blocking.
+    pub fn poll(&mut self) -> Option<ResponseResult<T>> {
+        let mut result_guard = self.slot.result.lock().unwrap();
+        Self::take(&mut self.taken, &mut result_guard)
+    }
+
+    /// Blocks until the host answers.
+    pub fn wait(mut self) -> ResponseResult<T> {
+        let mut result_guard = self.slot.result.lock().unwrap();
+        loop {
+            if let Some(result) = Self::take(&mut self.taken, &mut result_guard) {
+                return result;
+            }
+            result_guard = self.slot.ready.wait(result_guard).unwrap();
+        }
+    }
+
+    /// Blocks until the host answers, or until `timeout` elapses, in which
+    /// case [ResponseError::Timeout] is returned and the response may be
+    /// waited on again.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> ResponseResult<T> {
+        let result_guard = self.slot.result.lock().unwrap();
+        let taken = self.taken;
+        let (mut result_guard, _) = self
+            .slot
+            .ready
+            .wait_timeout_while(result_guard, timeout, |result| !taken && result.is_none())
+            .unwrap();
+
+        Self::take(&mut self.taken, &mut result_guard).unwrap_or(Err(ResponseError::Timeout))
+    }
+
+    fn take(
+        taken: &mut bool,
+        result_guard: &mut MutexGuard<Option<ResponseResult<T>>>,
+    ) -> Option<ResponseResult<T>> {
+        if *taken {
+            return Some(Err(ResponseError::Consumed));
+        }
+
+        let result = result_guard.take();
+        *taken = result.is_some();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static PENDING_BINDING: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn rejecting_host(_w: WindowInstance, _msg: *const AppMessage) -> u32 {
+        0
+    }
+
+    unsafe extern "C" fn deferring_host(_w: WindowInstance, msg: *const AppMessage) -> u32 {
+        if let AppMessage::QueryViewportExtents { binding } = &*msg {
+            PENDING_BINDING.store(*binding as usize, Ordering::SeqCst);
+        }
+        1
+    }
+
+    #[test]
+    fn response_test() {
+        let w = WindowInstance::from_raw(1);
+
+        let messenger = unsafe { WindowMessenger::from_raw(rejecting_host) };
+        assert_eq!(
+            messenger.request(w, QueryViewportExtents).wait(),
+            Err(ResponseError::NotDelivered)
+        );
+
+        let messenger = unsafe { WindowMessenger::from_raw(deferring_host) };
+        let mut response = messenger.request(w, QueryViewportExtents);
+        assert_eq!(response.poll(), None);
+        assert_eq!(
+            response.wait_timeout(Duration::from_millis(10)),
+            Err(ResponseError::Timeout)
+        );
+
+        let extent = Extent2D {
+            width: 32,
+            height: 16,
+        };
+        unsafe {
+            ResponseBinding::complete(
+                PENDING_BINDING.load(Ordering::SeqCst) as *const ResponseBinding,
+                RESPONSE_OK,
+                &extent as *const Extent2D as *const c_void,
+            )
+        };
+        assert_eq!(response.poll(), Some(Ok(extent)));
+        assert_eq!(response.poll(), Some(Err(ResponseError::Consumed)));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::{
+    renderer::{CapturedFrame, Renderer, RendererError},
+    vk,
+};
+use std::path::{Path, PathBuf};
+
+/// Set it to write the rendered images as the new references, rather than
+/// comparing against them.
+pub const UPDATE_REFERENCES_VAR: &str = "FAISCA_UPDATE_GOLDEN";
+
+/// The color differing pixels get in the diff images.
+const DIFF_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+#[derive(thiserror::Error, Debug)]
+pub enum GoldenError {
+    #[error("Failed to render the scene: {0}")]
+    Renderer(#[from] RendererError),
+    #[error("Failed to read {0}: {1}")]
+    ReadImage(PathBuf, std::io::Error),
+    #[error("Failed to decode {0}: {1}")]
+    DecodeImage(PathBuf, png::DecodingError),
+    #[error("{0} is not an 8 bit RGBA image")]
+    UnsupportedImage(PathBuf),
+    #[error("Failed to write {0}: {1}")]
+    WriteImage(PathBuf, std::io::Error),
+    #[error("Missing reference image {0}, run with {UPDATE_REFERENCES_VAR}=1 to create it")]
+    MissingReference(PathBuf),
+    #[error(
+        "The image is {actual_width}x{actual_height}, but the reference {reference} is \
+         {expected_width}x{expected_height}"
+    )]
+    SizeMismatch {
+        reference: PathBuf,
+        actual_width: u32,
+        actual_height: u32,
+        expected_width: u32,
+        expected_height: u32,
+    },
+    #[error("{differing} of {total} pixels differ from {reference}, see {diff}")]
+    Mismatch {
+        reference: PathBuf,
+        differing: usize,
+        total: usize,
+        diff: PathBuf,
+    },
+}
+
+/// How far a rendered image may be from its reference. Rasterization and
+/// rounding differ slightly between drivers, so demanding an exact match
+/// makes for flaky tests.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// How much each channel may differ before the pixel counts as
+    /// differing.
+    pub channel: u8,
+    /// The fraction of the pixels that may differ, from 0 to 1.
+    pub max_differing_ratio: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            max_differing_ratio: 0.001,
+        }
+    }
+}
+
+/// The result of [compare].
+#[derive(Debug)]
+pub struct Comparison {
+    pub differing_pixels: usize,
+    pub total_pixels: usize,
+    /// The expected image, dimmed to gray, with the differing pixels
+    /// highlighted.
+    pub diff: CapturedFrame,
+}
+
+/// Renders a frame of the scene to an offscreen image, in RGBA8. `setup`
+/// gets the renderer before the frame is drawn, to set the scene up.
+pub fn render_offscreen(
+    extent: vk::Extent2D,
+    setup: impl FnOnce(&mut Renderer) -> Result<(), RendererError>,
+) -> Result<CapturedFrame, RendererError> {
+    let mut renderer = Renderer::new_offscreen(extent, vk::Format::R8G8B8A8_UNORM)?;
+    setup(&mut renderer)?;
+
+    renderer.capture_next_frame();
+    renderer.draw_frame()?;
+    Ok(renderer
+        .take_captured_frame()
+        .expect("An offscreen frame is captured as it is drawn"))
+}
+
+/// Compares two images of the same size pixel by pixel.
+pub fn compare(
+    actual: &CapturedFrame,
+    expected: &CapturedFrame,
+    channel_tolerance: u8,
+) -> Comparison {
+    assert_eq!(
+        (actual.width, actual.height),
+        (expected.width, expected.height)
+    );
+
+    let mut differing_pixels = 0;
+    let mut diff_pixels = Vec::with_capacity(expected.pixels.len());
+    for (actual_px, expected_px) in actual
+        .pixels
+        .chunks_exact(4)
+        .zip(expected.pixels.chunks_exact(4))
+    {
+        let differs = actual_px
+            .iter()
+            .zip(expected_px)
+            .any(|(&a, &e)| a.abs_diff(e) > channel_tolerance);
+
+        if differs {
+            differing_pixels += 1;
+            diff_pixels.extend_from_slice(&DIFF_COLOR);
+        } else {
+            let gray = ((u16::from(expected_px[0])
+                + u16::from(expected_px[1])
+                + u16::from(expected_px[2]))
+                / 9) as u8;
+            diff_pixels.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+
+    Comparison {
+        differing_pixels,
+        total_pixels: expected.pixels.len() / 4,
+        diff: CapturedFrame {
+            width: expected.width,
+            height: expected.height,
+            pixels: diff_pixels,
+        },
+    }
+}
+
+/// Checks the image against the reference PNG at `reference`. If they
+/// differ too much, the image and a diff are written next to the reference,
+/// as `<name>.actual.png` and `<name>.diff.png`.
+///
+/// With [UPDATE_REFERENCES_VAR] set, the image is written as the reference
+/// instead.
+pub fn check_against_reference(
+    actual: &CapturedFrame,
+    reference: &Path,
+    tolerance: Tolerance,
+) -> Result<(), GoldenError> {
+    if std::env::var_os(UPDATE_REFERENCES_VAR).is_some() {
+        log::info!("Updating reference image {}", reference.display());
+        return write_png(actual, reference);
+    }
+
+    if !reference.exists() {
+        write_png(actual, &sibling_path(reference, "actual"))?;
+        return Err(GoldenError::MissingReference(reference.to_owned()));
+    }
+
+    let expected = read_png(reference)?;
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        write_png(actual, &sibling_path(reference, "actual"))?;
+        return Err(GoldenError::SizeMismatch {
+            reference: reference.to_owned(),
+            actual_width: actual.width,
+            actual_height: actual.height,
+            expected_width: expected.width,
+            expected_height: expected.height,
+        });
+    }
+
+    let comparison = compare(actual, &expected, tolerance.channel);
+    let max_differing = comparison.total_pixels as f64 * tolerance.max_differing_ratio;
+    if comparison.differing_pixels as f64 > max_differing {
+        let diff = sibling_path(reference, "diff");
+        write_png(actual, &sibling_path(reference, "actual"))?;
+        write_png(&comparison.diff, &diff)?;
+        return Err(GoldenError::Mismatch {
+            reference: reference.to_owned(),
+            differing: comparison.differing_pixels,
+            total: comparison.total_pixels,
+            diff,
+        });
+    }
+
+    Ok(())
+}
+
+/// `dir/name.png` becomes `dir/name.<suffix>.png`.
+fn sibling_path(reference: &Path, suffix: &str) -> PathBuf {
+    let stem = reference.file_stem().unwrap_or_default().to_string_lossy();
+    reference.with_file_name(format!("{stem}.{suffix}.png"))
+}
+
+fn read_png(path: &Path) -> Result<CapturedFrame, GoldenError> {
+    let file = std::fs::File::open(path).map_err(|e| GoldenError::ReadImage(path.to_owned(), e))?;
+    let mut reader = png::Decoder::new(std::io::BufReader::new(file))
+        .read_info()
+        .map_err(|e| GoldenError::DecodeImage(path.to_owned(), e))?;
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut pixels)
+        .map_err(|e| GoldenError::DecodeImage(path.to_owned(), e))?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(GoldenError::UnsupportedImage(path.to_owned()));
+    }
+    pixels.truncate(info.buffer_size());
+
+    Ok(CapturedFrame {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+fn write_png(frame: &CapturedFrame, path: &Path) -> Result<(), GoldenError> {
+    frame
+        .save_png(path)
+        .map_err(|e| GoldenError::WriteImage(path.to_owned(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::{Point2DColorRGBVertex, Vector2, Vector3};
+
+    /// Set it to fail the rendering tests when there is no usable Vulkan
+    /// driver, rather than skipping them.
+    const REQUIRE_VULKAN_VAR: &str = "FAISCA_REQUIRE_VULKAN";
+
+    #[rustfmt::skip]
+    const QUAD_VERTICES: [Point2DColorRGBVertex; 4] = [
+        Point2DColorRGBVertex { point: Vector2([ 0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
+        Point2DColorRGBVertex { point: Vector2([ 0.5,  0.5]), color: Vector3([ 0.0,  1.0,  0.0]) },
+        Point2DColorRGBVertex { point: Vector2([-0.5,  0.5]), color: Vector3([ 0.0,  0.0,  1.0]) },
+        Point2DColorRGBVertex { point: Vector2([-0.5, -0.5]), color: Vector3([ 1.0,  0.0,  0.0]) },
+    ];
+
+    #[test]
+    fn comparison_test() {
+        let expected = CapturedFrame {
+            width: 2,
+            height: 1,
+            pixels: vec![90, 90, 90, 255, 0, 0, 0, 255],
+        };
+        let actual = CapturedFrame {
+            width: 2,
+            height: 1,
+            pixels: vec![92, 88, 90, 255, 0, 10, 0, 255],
+        };
+
+        let comparison = compare(&actual, &expected, 2);
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.total_pixels, 2);
+        assert_eq!(comparison.diff.pixels, [30, 30, 30, 255, 255, 0, 255, 255]);
+
+        assert_eq!(compare(&actual, &expected, 10).differing_pixels, 0);
+    }
+
+    #[test]
+    fn test_quad_golden_test() {
+        let extent = vk::Extent2D {
+            width: 64,
+            height: 64,
+        };
+        let setup = |renderer: &mut Renderer| {
+            renderer
+                .create_mesh(&QUAD_VERTICES, &[0, 1, 2, 2, 3, 0])
+                .map(|_| ())
+        };
+        let frame = match render_offscreen(extent, setup) {
+            Ok(frame) => frame,
+            // Only a missing driver or GPU skips the test, any other error
+            // is a bug
+            Err(
+                e @ (RendererError::FailedToLoadDriver(_) | RendererError::NoAvailableVideoAdapter),
+            ) if std::env::var_os(REQUIRE_VULKAN_VAR).is_none() => {
+                log::warn!(
+                    "Skipping, no usable Vulkan driver ({e}), set {REQUIRE_VULKAN_VAR} to fail"
+                );
+                return;
+            }
+            Err(e) => panic!("{e}"),
+        };
+
+        let reference = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/test_quad.png");
+        check_against_reference(&frame, &reference, Tolerance::default())
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+}
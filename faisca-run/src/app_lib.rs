@@ -0,0 +1,72 @@
+use faisca::{ffi::ABI_VERSION, MessageAppFn, MessageWindowFn, RunAppFn, WindowInstance};
+use libloading::Library;
+use std::path::Path;
+
+type AbiVersionFn = unsafe extern "C" fn() -> u32;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AppLibraryError {
+    #[error("Failed to load the app library: {0}")]
+    FailedToLoad(libloading::Error),
+    #[error("The app library does not export {0}: {1}")]
+    MissingSymbol(&'static str, libloading::Error),
+    #[error(
+        "The app library uses ABI version {app}, but this host uses version {host}. Both must be \
+         built from the same Faisca version"
+    )]
+    AbiMismatch { app: u32, host: u32 },
+}
+
+/// An app library (a `cdylib` using [app_entry](faisca::app_entry)) and the
+/// functions it exports. They are only valid for as long as it is loaded.
+pub struct AppLibrary {
+    run_app: RunAppFn,
+    message_app: MessageAppFn,
+    _library: Library,
+}
+
+impl AppLibrary {
+    pub fn load(path: &Path) -> Result<AppLibrary, AppLibraryError> {
+        let library = unsafe { Library::new(path) }.map_err(AppLibraryError::FailedToLoad)?;
+
+        // We must not send a single message with a layout the app doesn't
+        // expect, so the version comes first
+        let abi_version: AbiVersionFn = unsafe { symbol(&library, "faisca_abi_version") }?;
+        let app_abi_version = unsafe { abi_version() };
+        if app_abi_version != ABI_VERSION {
+            return Err(AppLibraryError::AbiMismatch {
+                app: app_abi_version,
+                host: ABI_VERSION,
+            });
+        }
+
+        Ok(AppLibrary {
+            run_app: unsafe { symbol(&library, "faisca_run_app") }?,
+            message_app: unsafe { symbol(&library, "faisca_message_app") }?,
+            _library: library,
+        })
+    }
+
+    #[inline]
+    pub fn message_app_fn(&self) -> MessageAppFn {
+        self.message_app
+    }
+
+    /// Runs the app until it returns. This blocks, so it is meant to be
+    /// called on a thread of its own.
+    pub fn run(&self, w: WindowInstance, message_window: MessageWindowFn) {
+        unsafe { (self.run_app)(w, message_window) };
+    }
+}
+
+/// Looks up a function exported by `library`.
+///
+/// # Safety
+/// `T` must be the type of the exported function.
+unsafe fn symbol<T: Copy>(library: &Library, name: &'static str) -> Result<T, AppLibraryError> {
+    let symbol_name = format!("{name}\0");
+    library
+        .get::<T>(symbol_name.as_bytes())
+        .map(|symbol| *symbol)
+        .map_err(|e| AppLibraryError::MissingSymbol(name, e))
+}
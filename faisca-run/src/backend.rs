@@ -0,0 +1,76 @@
+use faisca::{
+    host::headless::{HeadlessError, HeadlessHost},
+    MessageAppFn, MessageWindowFn, WindowInstance,
+};
+use std::time::{Duration, Instant};
+
+/// A window system the runner can drive an app with. The backend owns the
+/// windows, handles the messages the app sends through
+/// [message_window_fn](HostBackend::message_window_fn), and delivers events
+/// through the app's `MessageAppFn`, doing the handshake with it first.
+///
+/// The app runs on a thread of its own, while the backend lives on the main
+/// thread, as most window systems want.
+pub trait HostBackend {
+    /// The window the app is started with.
+    fn main_window(&self) -> WindowInstance;
+
+    /// The function handed to the app, for sending messages to the backend.
+    fn message_window_fn(&self) -> MessageWindowFn;
+
+    /// Called over and over on the main thread for as long as the app runs.
+    /// This is where window system events are turned into app events.
+    fn pump_events(&mut self);
+
+    /// Returns why the app panicked, if it reported a panic.
+    fn app_panic(&self) -> Option<String>;
+}
+
+/// Runs the app against a [HeadlessHost], with no actual window. Rendering
+/// needs a Vulkan driver with `VK_EXT_headless_surface`, such as Mesa's.
+pub struct HeadlessBackend {
+    host: HeadlessHost,
+    /// When to ask the app to quit, as nothing else would.
+    quit_at: Option<Instant>,
+}
+
+impl HeadlessBackend {
+    pub fn new(
+        message_app: MessageAppFn,
+        quit_after: Option<Duration>,
+    ) -> Result<HeadlessBackend, HeadlessError> {
+        Ok(HeadlessBackend {
+            host: HeadlessHost::new(message_app)?,
+            quit_at: quit_after.map(|quit_after| Instant::now() + quit_after),
+        })
+    }
+}
+
+impl HostBackend for HeadlessBackend {
+    #[inline]
+    fn main_window(&self) -> WindowInstance {
+        self.host.window()
+    }
+
+    #[inline]
+    fn message_window_fn(&self) -> MessageWindowFn {
+        self.host.message_window_fn()
+    }
+
+    fn pump_events(&mut self) {
+        // Messages are handled as they are sent, so the only event we have
+        // to generate is the quit
+        if self
+            .quit_at
+            .is_some_and(|quit_at| Instant::now() >= quit_at)
+        {
+            log::info!("Asking the app to quit");
+            self.host.quit();
+            self.quit_at = None;
+        }
+    }
+
+    fn app_panic(&self) -> Option<String> {
+        self.host.app_panic()
+    }
+}
@@ -0,0 +1,150 @@
+mod app_lib;
+mod backend;
+
+use app_lib::{AppLibrary, AppLibraryError};
+use backend::{HeadlessBackend, HostBackend};
+use faisca::{host::headless::HeadlessError, MessageAppFn};
+use std::{path::PathBuf, time::Duration};
+
+const USAGE: &str = "Usage: faisca-run <app library> [--backend headless] [--quit-after <seconds>]";
+
+/// How long to wait between two event pumps.
+const PUMP_INTERVAL: Duration = Duration::from_millis(8);
+
+#[derive(thiserror::Error, Debug)]
+enum RunError {
+    #[error("{0}\n{USAGE}")]
+    InvalidArguments(String),
+    #[error("Unknown backend: {0}")]
+    UnknownBackend(String),
+    #[error(transparent)]
+    AppLibrary(#[from] AppLibraryError),
+    #[error("Failed to start the headless backend: {0}")]
+    Headless(#[from] HeadlessError),
+    #[error("The app panicked: {0}")]
+    AppPanicked(String),
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    library: PathBuf,
+    backend: String,
+    quit_after: Option<Duration>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, RunError> {
+    let mut library = None;
+    let mut backend = String::from("headless");
+    let mut quit_after = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--backend" => {
+                backend = args
+                    .next()
+                    .ok_or_else(|| RunError::InvalidArguments(String::from("Missing backend")))?;
+            }
+            "--quit-after" => {
+                let seconds = args
+                    .next()
+                    .and_then(|seconds| seconds.parse::<f64>().ok())
+                    .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                    .ok_or_else(|| {
+                        RunError::InvalidArguments(String::from("Invalid --quit-after seconds"))
+                    })?;
+                quit_after = Some(seconds);
+            }
+            _ if library.is_none() && !arg.starts_with("--") => library = Some(PathBuf::from(arg)),
+            _ => {
+                return Err(RunError::InvalidArguments(format!(
+                    "Unexpected argument: {arg}"
+                )))
+            }
+        }
+    }
+
+    Ok(Options {
+        library: library
+            .ok_or_else(|| RunError::InvalidArguments(String::from("Missing app library")))?,
+        backend,
+        quit_after,
+    })
+}
+
+fn create_backend(
+    options: &Options,
+    message_app: MessageAppFn,
+) -> Result<Box<dyn HostBackend>, RunError> {
+    match options.backend.as_str() {
+        "headless" => Ok(Box::new(HeadlessBackend::new(
+            message_app,
+            options.quit_after,
+        )?)),
+        other => Err(RunError::UnknownBackend(other.to_owned())),
+    }
+}
+
+fn run(options: Options) -> Result<(), RunError> {
+    let app = AppLibrary::load(&options.library)?;
+    // Declared after the library, so that it is dropped before the library is
+    // unloaded
+    let mut backend = create_backend(&options, app.message_app_fn())?;
+
+    let main_window = backend.main_window();
+    let message_window = backend.message_window_fn();
+    std::thread::scope(|scope| {
+        let app_thread = scope.spawn(|| app.run(main_window, message_window));
+        while !app_thread.is_finished() {
+            backend.pump_events();
+            std::thread::sleep(PUMP_INTERVAL);
+        }
+    });
+
+    match backend.app_panic() {
+        Some(message) => Err(RunError::AppPanicked(message)),
+        None => Ok(()),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    if let Err(e) = parse_args(std::env::args().skip(1)).and_then(run) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, RunError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn args_test() {
+        assert_eq!(
+            parse(&["libapp.so"]).unwrap(),
+            Options {
+                library: PathBuf::from("libapp.so"),
+                backend: String::from("headless"),
+                quit_after: None,
+            }
+        );
+        assert_eq!(
+            parse(&["--quit-after", "1.5", "libapp.so", "--backend", "sdl"]).unwrap(),
+            Options {
+                library: PathBuf::from("libapp.so"),
+                backend: String::from("sdl"),
+                quit_after: Some(Duration::from_millis(1500)),
+            }
+        );
+
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["libapp.so", "libother.so"]).is_err());
+        assert!(parse(&["libapp.so", "--quit-after", "-1"]).is_err());
+        assert!(parse(&["libapp.so", "--backend"]).is_err());
+    }
+}
@@ -19,23 +19,91 @@ namespace faisca {
         FULLSCREEN_DESKTOP = 2,
     };
 
+    // The values match SDL_SystemCursor
+    enum SystemCursorType {
+        SYSTEM_CURSOR_ARROW = 0,
+        SYSTEM_CURSOR_IBEAM,
+        SYSTEM_CURSOR_WAIT,
+        SYSTEM_CURSOR_CROSSHAIR,
+        SYSTEM_CURSOR_WAITARROW,
+        SYSTEM_CURSOR_SIZENWSE,
+        SYSTEM_CURSOR_SIZENESW,
+        SYSTEM_CURSOR_SIZEWE,
+        SYSTEM_CURSOR_SIZENS,
+        SYSTEM_CURSOR_SIZEALL,
+        SYSTEM_CURSOR_NO,
+        SYSTEM_CURSOR_HAND,
+        SYSTEM_CURSOR_COUNT,
+    };
+
     enum BooleanType {
         FAISCA_FALSE = 0,
         FAISCA_TRUE = 1,
     };
 
+    // Opaque to the host, it is only handed back to the app with the response
+    struct ResponseBinding;
+
+    enum ResponseStatus {
+        RESPONSE_OK = 0,
+        RESPONSE_FAILED = -1,
+    };
+
+    struct DisplayMode {
+        uint32_t width;
+        uint32_t height;
+        // In Hz, zero if unknown
+        uint32_t refreshRate;
+    };
+
+    struct DisplayBounds {
+        int32_t x;
+        int32_t y;
+        uint32_t width;
+        uint32_t height;
+    };
+
+    struct RawDisplay {
+        const char *name;
+        DisplayBounds bounds;
+        DisplayMode currentMode;
+        const DisplayMode *modes;
+        size_t modeCount;
+    };
+
+    // The answer to APPMSG_QUERY_DISPLAYS, only needs to be valid until the
+    // app returns
+    struct RawDisplayList {
+        const RawDisplay *displays;
+        size_t count;
+    };
+
     enum AppMessageType {
         APPMSG_SET_WINDOW_SIZE = 1,
         APPMSG_SET_FULLSCREEN,
         APPMSG_SET_BORDERLESS,
         APPMSG_SET_WINDOW_TITLE,
         APPMSG_SET_WINDOW_RESIZABLE,
+        APPMSG_SET_CLIPBOARD_TEXT,
+        APPMSG_SET_CURSOR_VISIBLE,
+        APPMSG_SET_RELATIVE_MOUSE_MODE,
+        APPMSG_SET_SYSTEM_CURSOR,
+        APPMSG_WARP_MOUSE,
+        APPMSG_RUMBLE_CONTROLLER,
+        APPMSG_SET_WINDOW_ICON,
 
         APPMSG_CREATE_VULKAN_SURFACE = 1025,
         APPMSG_QUERY_VIEWPORT_EXTENT,
         APPMSG_SET_MSG_BACKCHANNEL,
+        APPMSG_CREATE_WINDOW,
+        APPMSG_DESTROY_WINDOW,
+        APPMSG_QUERY_CLIPBOARD_TEXT,
+        APPMSG_QUERY_DISPLAY_SCALE,
+        APPMSG_QUERY_DISPLAYS,
 
         APPMSG_PUMP_EVENTS = 2049,
+        APPMSG_APP_PANICKED,
+        APPMSG_CUSTOM,
     };
 
     struct AppMessage {
@@ -45,59 +113,222 @@ namespace faisca {
                 uint32_t width;
                 uint32_t height;
             } windowSize;
-            uint8_t fullscreen;
+            struct {
+                uint8_t type;
+                // Only used by FULLSCREEN_REAL
+                uint32_t display;
+                DisplayMode mode;
+            } fullscreen;
             uint8_t borderless;
             const char *windowTitle;
             uint8_t windowResizable;
+            const char *clipboardText;
+            const char *panicMessage;
+            // For host plugins, the payload only needs to be valid until the
+            // host returns
+            struct {
+                uint32_t kind;
+                const uint8_t *payload;
+                size_t len;
+            } custom;
+            uint8_t cursorVisible;
+            uint8_t relativeMouseMode;
+            uint8_t systemCursor;
+            struct {
+                int32_t x;
+                int32_t y;
+            } warpMouse;
+            struct {
+                int32_t id;
+                uint16_t low;
+                uint16_t high;
+                uint32_t durationMs;
+            } rumbleController;
+            struct {
+                uint32_t width;
+                uint32_t height;
+                // width * height RGBA pixels, 8 bits per channel
+                const uint8_t *pixels;
+            } windowIcon;
 
             struct {
                 uint64_t instance_handle;
-                struct {
-                    void *out;
-                    const void *barrier;
-                } *responseBinding;
+                const ResponseBinding *responseBinding;
             } windowSurfaceCreateInfo;
-            struct {
-                void *out;
-                const void *barrier;
-            } *queryResponseBinding;
+            // Used by every query that only carries its binding
+            const ResponseBinding *queryResponseBinding;
             void *msgBackchannel;
+            struct {
+                uint32_t width;
+                uint32_t height;
+                const ResponseBinding *responseBinding;
+            } createWindow;
         };
     };
 
+    enum MouseButton {
+        MOUSE_BUTTON_LEFT = 1,
+        MOUSE_BUTTON_MIDDLE = 2,
+        MOUSE_BUTTON_RIGHT = 3,
+        MOUSE_BUTTON_X1 = 4,
+        MOUSE_BUTTON_X2 = 5,
+    };
+
     enum WindowEventType {
         WINEVT_QUIT = 1,
         WINEVT_WINDOW_RESIZE,
+        WINEVT_FOCUS_GAINED,
+        WINEVT_FOCUS_LOST,
+        WINEVT_MINIMIZED,
+        WINEVT_RESTORED,
+        WINEVT_MOVED,
+        WINEVT_EXPOSED,
+        WINEVT_CLOSE_REQUESTED,
+        WINEVT_SURFACE_LOST,
+        WINEVT_SURFACE_RECREATED,
+        WINEVT_APP_WILL_ENTER_BACKGROUND,
+        WINEVT_APP_DID_ENTER_FOREGROUND,
+        WINEVT_SCALE_FACTOR_CHANGED,
+        WINEVT_DROP_BEGIN,
+        WINEVT_DROP_FILE,
+        WINEVT_DROP_COMPLETE,
+        WINEVT_RELOAD_REQUESTED,
+
+        WINEVT_KEY_DOWN = 1025,
+        WINEVT_KEY_UP,
+        WINEVT_MOUSE_MOTION,
+        WINEVT_MOUSE_BUTTON,
+        WINEVT_MOUSE_WHEEL,
+        WINEVT_CONTROLLER_ADDED,
+        WINEVT_CONTROLLER_REMOVED,
+        WINEVT_CONTROLLER_BUTTON,
+        WINEVT_CONTROLLER_AXIS,
     };
 
     struct WindowEvent {
         uint32_t type;
         union {
+            // (w, h) is the logical size, the pixel size may be larger on HiDPI
             struct {
                 uint32_t w;
                 uint32_t h;
+                uint32_t pixelW;
+                uint32_t pixelH;
             } windowResize;
+            float scaleFactor;
+            // Owned by the host, the app copies it
+            const char *dropFile;
+            struct {
+                int32_t x;
+                int32_t y;
+            } windowMoved;
+
+            // Used by both WINEVT_KEY_DOWN and WINEVT_KEY_UP
+            struct {
+                uint32_t scancode;
+                int32_t keycode;
+                uint16_t modifiers;
+                uint8_t repeat;
+            } key;
+            struct {
+                int32_t x;
+                int32_t y;
+                int32_t xrel;
+                int32_t yrel;
+            } mouseMotion;
+            struct {
+                uint8_t button;
+                uint8_t pressed;
+                uint8_t clicks;
+                int32_t x;
+                int32_t y;
+            } mouseButton;
+            struct {
+                float x;
+                float y;
+            } mouseWheel;
+            // Used by both WINEVT_CONTROLLER_ADDED and WINEVT_CONTROLLER_REMOVED
+            struct {
+                int32_t id;
+            } controllerDevice;
+            struct {
+                int32_t id;
+                uint8_t button;
+                uint8_t pressed;
+            } controllerButton;
+            struct {
+                int32_t id;
+                uint8_t axis;
+                int16_t value;
+            } controllerAxis;
         };
     };
 
+    // Must match `ABI_VERSION` in the app's `ffi.rs`, and be bumped whenever
+    // any of the message layouts changes.
+    constexpr uint32_t ABI_VERSION = 12;
+
+    // Must match `MAX_WINDOW_ICON_SIDE` in the app's `ffi.rs`. Icons with a
+    // longer side, or an empty one, are rejected.
+    constexpr uint32_t MAX_WINDOW_ICON_SIDE = 4096;
+
+    enum Capability : uint64_t {
+        CAPABILITY_MULTI_WINDOW = 1 << 0,
+        CAPABILITY_CLIPBOARD = 1 << 1,
+        CAPABILITY_CURSOR = 1 << 2,
+        CAPABILITY_GAMEPAD = 1 << 3,
+        CAPABILITY_HOT_RELOAD = 1 << 4,
+    };
+
+    enum MessageAppResult {
+        MESSAGE_ACCEPTED = 0,
+        MESSAGE_REJECTED_NO_HANDSHAKE = 1,
+        MESSAGE_REJECTED_ABI_MISMATCH = 2,
+        MESSAGE_PANICKED = 3,
+        MESSAGE_UNHANDLED = 4,
+    };
+
+    enum StateStatus {
+        STATE_OK = 0,
+        STATE_FAILED = 1,
+    };
+
     enum WindowMessageType {
+        // The layout of this message must never change
+        WINMSG_HELLO = 0,
         WINMSG_VULKAN_INSTANCE_REQUIRED_EXTENSIONS = 1,
         WINMSG_RESPONSE_NOTIFY,
         WINMSG_WINDOW_EVENT,
+        WINMSG_CUSTOM,
     };
 
     struct WindowMessage {
         uint32_t type;
         union {
+            struct {
+                uint32_t abiVersion;
+                uint64_t capabilities;
+            } hello;
             struct {
                 const char *const *names;
                 size_t count;
             } vk_instance_required_ext;
-            void *responseNotifyBinding;
+            struct {
+                const ResponseBinding *binding;
+                int32_t status;
+                // Only needs to be valid until the app returns
+                const void *data;
+            } responseNotify;
             struct {
                 void *msgBackchannel;
                 const WindowEvent *windowEvent;
             } windowEvent;
+            // Only needs to be valid until the app returns
+            struct {
+                uint32_t kind;
+                const uint8_t *payload;
+                size_t len;
+            } custom;
         };
     };
 
@@ -112,6 +343,11 @@ namespace faisca {
 
     typedef void (ECABI *FnRunApp)(WindowInstance, FnMessageWindow);
     typedef uint32_t (ECABI *FnMessageApp)(WindowInstance, const WindowMessage*);
+    typedef uint32_t (ECABI *FnAbiVersion)(void);
+    // Called by the app with its saved state, which the host must copy
+    typedef void (ECABI *FnStateSink)(void *user, const uint8_t *data, size_t len);
+    typedef uint32_t (ECABI *FnSaveState)(FnStateSink sink, void *user);
+    typedef uint32_t (ECABI *FnLoadState)(const uint8_t *data, size_t len);
 
     typedef int32_t (ECABI *FnSurfaceCreate)(WindowInstance, VkInstance, VkSurfaceKHR*);
     typedef int32_t (ECABI *FnWindowGetExtent)(WindowInstance, VkExtent2D*);
@@ -0,0 +1,30 @@
+#ifndef FAISCA_PLUGINS_HPP_
+#define FAISCA_PLUGINS_HPP_
+
+#include <functional>
+#include <SDL.h>
+
+#include <defines.hpp>
+
+namespace faisca {
+    // Handles a custom message from the app, whose payload is only valid
+    // during the call. It may answer with sendCustomMessage.
+    typedef std::function<void(FnMessageApp, SDL_Window*, const uint8_t*, size_t)> CustomHandler;
+
+    // Host plugins (achievements, analytics...) call this before the app
+    // runs, to handle the custom messages of `kind`. Handlers run on the
+    // event thread.
+    void registerCustomHandler(uint32_t kind, CustomHandler handler);
+
+    // Returns one of the MESSAGE_* values, MESSAGE_UNHANDLED if the app has
+    // no handler for `kind`
+    uint32_t sendCustomMessage(
+        FnMessageApp messageApp,
+        SDL_Window *window,
+        uint32_t kind,
+        const uint8_t *payload,
+        size_t len
+    );
+}
+
+#endif // FAISCA_PLUGINS_HPP_
@@ -1,16 +1,22 @@
+#include <atomic>
 #include <chrono>
 #include <condition_variable>
 #include <cstdlib>
 #include <cstring>
+#include <filesystem>
 #include <iostream>
+#include <map>
+#include <memory>
 #include <mutex>
 #include <SDL.h>
 #include <SDL_vulkan.h>
+#include <string>
 #include <thread>
 #include <vector>
 
 #include <defines.hpp>
 #include <dylib.hpp>
+#include <plugins.hpp>
 
 using namespace faisca;
 using namespace std::chrono_literals;
@@ -21,8 +27,230 @@ static std::mutex gEventWaitMutex;
 static std::condition_variable gEventWaitFence;
 static bool gEventWaitFlag = false;
 
+// Open controllers, keyed by joystick instance id, which is the id the app sees
+static std::map<SDL_JoystickID, SDL_GameController*> gControllers;
+
+// Created on first use, and freed when the host exits
+static SDL_Cursor *gSystemCursors[SYSTEM_CURSOR_COUNT] = {};
+
+static void setSystemCursor(uint8_t systemCursor) {
+    if (systemCursor >= SYSTEM_CURSOR_COUNT) {
+        std::cerr << "Unknown system cursor: " << (int) systemCursor << std::endl;
+        return;
+    }
+
+    if (gSystemCursors[systemCursor] == nullptr) {
+        gSystemCursors[systemCursor] = SDL_CreateSystemCursor((SDL_SystemCursor) systemCursor);
+        if (gSystemCursors[systemCursor] == nullptr) {
+            std::cerr << "Failed to create system cursor: " << SDL_GetError() << std::endl;
+            return;
+        }
+    }
+    SDL_SetCursor(gSystemCursors[systemCursor]);
+}
+
+static void setFullscreen(SDL_Window *window, uint8_t type, uint32_t display, const DisplayMode &mode) {
+    if (type == FULLSCREEN_NONE) {
+        SDL_SetWindowFullscreen(window, 0);
+        return;
+    } else if (type != FULLSCREEN_REAL) {
+        SDL_SetWindowFullscreen(window, SDL_WINDOW_FULLSCREEN_DESKTOP);
+        return;
+    }
+
+    SDL_DisplayMode sdlMode {};
+    bool found = false;
+    int modeCount = SDL_GetNumDisplayModes(display);
+    for (int i = 0; i < modeCount && !found; i++) {
+        if (SDL_GetDisplayMode(display, i, &sdlMode) == 0) {
+            found = (uint32_t) sdlMode.w == mode.width
+                && (uint32_t) sdlMode.h == mode.height
+                && (uint32_t) sdlMode.refresh_rate == mode.refreshRate;
+        }
+    }
+    if (!found) {
+        std::cerr << "Display " << display << " has no " << mode.width << "x" << mode.height
+            << "@" << mode.refreshRate << " mode" << std::endl;
+        return;
+    }
+
+    // The window goes fullscreen on the display it is in, so we move it there
+    // first
+    SDL_SetWindowFullscreen(window, 0);
+    SDL_SetWindowPosition(window, SDL_WINDOWPOS_CENTERED_DISPLAY(display), SDL_WINDOWPOS_CENTERED_DISPLAY(display));
+    if (SDL_SetWindowDisplayMode(window, &sdlMode) != 0
+        || SDL_SetWindowFullscreen(window, SDL_WINDOW_FULLSCREEN) != 0) {
+        std::cerr << "Failed to set fullscreen mode: " << SDL_GetError() << std::endl;
+    }
+}
+
+static void sendWindowEvent(
+    FnMessageApp messageApp,
+    void *backChannel,
+    SDL_Window *window,
+    const WindowEvent &windowEvent
+) {
+    WindowMessage windowMessage {};
+    windowMessage.type = WINMSG_WINDOW_EVENT;
+    windowMessage.windowEvent.msgBackchannel = backChannel;
+    windowMessage.windowEvent.windowEvent = &windowEvent;
+
+    messageApp(window, &windowMessage);
+}
+
+// The last scale factor reported for each window, keyed by window id
+static std::map<Uint32, float> gWindowScales;
+
+static std::map<uint32_t, CustomHandler> gCustomHandlers;
+
+void faisca::registerCustomHandler(uint32_t kind, CustomHandler handler) {
+    gCustomHandlers[kind] = std::move(handler);
+}
+
+uint32_t faisca::sendCustomMessage(
+    FnMessageApp messageApp,
+    SDL_Window *window,
+    uint32_t kind,
+    const uint8_t *payload,
+    size_t len
+) {
+    WindowMessage message = {};
+    message.type = WINMSG_CUSTOM;
+    message.custom.kind = kind;
+    message.custom.payload = payload;
+    message.custom.len = len;
+    return messageApp(window, &message);
+}
+
+// How many pixels there are to each unit of the logical window size
+static float windowScale(SDL_Window *window) {
+    int width, height, pixelWidth, pixelHeight;
+    SDL_GetWindowSize(window, &width, &height);
+    SDL_GetWindowSizeInPixels(window, &pixelWidth, &pixelHeight);
+    return width > 0 ? (float) pixelWidth / (float) width : 1.0f;
+}
+
+// Reports the window size, preceded by its scale factor if it changed since
+// it was last reported
+static void sendWindowResize(
+    FnMessageApp messageApp,
+    void *backChannel,
+    SDL_Window *window
+) {
+    float scale = windowScale(window);
+    auto found = gWindowScales.find(SDL_GetWindowID(window));
+    if (found == gWindowScales.end() || found->second != scale) {
+        gWindowScales[SDL_GetWindowID(window)] = scale;
+
+        WindowEvent scaleEvent {};
+        scaleEvent.type = WINEVT_SCALE_FACTOR_CHANGED;
+        scaleEvent.scaleFactor = scale;
+        sendWindowEvent(messageApp, backChannel, window, scaleEvent);
+    }
+
+    int width, height, pixelWidth, pixelHeight;
+    SDL_GetWindowSize(window, &width, &height);
+    SDL_GetWindowSizeInPixels(window, &pixelWidth, &pixelHeight);
+
+    WindowEvent windowEvent {};
+    windowEvent.type = WINEVT_WINDOW_RESIZE;
+    windowEvent.windowResize.w = width;
+    windowEvent.windowResize.h = height;
+    windowEvent.windowResize.pixelW = pixelWidth;
+    windowEvent.windowResize.pixelH = pixelHeight;
+    sendWindowEvent(messageApp, backChannel, window, windowEvent);
+}
+
+static void sendResponse(
+    FnMessageApp messageApp,
+    SDL_Window *window,
+    const ResponseBinding *binding,
+    int32_t status,
+    const void *data
+) {
+    WindowMessage message {};
+    message.type = WINMSG_RESPONSE_NOTIFY;
+    message.responseNotify.binding = binding;
+    message.responseNotify.status = status;
+    message.responseNotify.data = data;
+
+    messageApp(window, &message);
+}
+
+static void sendDisplays(FnMessageApp messageApp, SDL_Window *window, const ResponseBinding *binding) {
+    int displayCount = SDL_GetNumVideoDisplays();
+    if (displayCount < 0) {
+        std::cerr << "Failed to query displays: " << SDL_GetError() << std::endl;
+        sendResponse(messageApp, window, binding, RESPONSE_FAILED, nullptr);
+        return;
+    }
+
+    std::vector<std::vector<DisplayMode>> modes(displayCount);
+    std::vector<RawDisplay> displays(displayCount);
+    for (int i = 0; i < displayCount; i++) {
+        SDL_Rect bounds {};
+        SDL_GetDisplayBounds(i, &bounds);
+        SDL_DisplayMode currentMode {};
+        SDL_GetCurrentDisplayMode(i, &currentMode);
+
+        int modeCount = SDL_GetNumDisplayModes(i);
+        for (int j = 0; j < modeCount; j++) {
+            SDL_DisplayMode sdlMode {};
+            if (SDL_GetDisplayMode(i, j, &sdlMode) == 0) {
+                modes[i].push_back(DisplayMode { (uint32_t) sdlMode.w, (uint32_t) sdlMode.h, (uint32_t) sdlMode.refresh_rate });
+            }
+        }
+
+        RawDisplay &display = displays[i];
+        display.name = SDL_GetDisplayName(i);
+        display.bounds = DisplayBounds { bounds.x, bounds.y, (uint32_t) bounds.w, (uint32_t) bounds.h };
+        display.currentMode = DisplayMode { (uint32_t) currentMode.w, (uint32_t) currentMode.h, (uint32_t) currentMode.refresh_rate };
+        display.modes = modes[i].data();
+        display.modeCount = modes[i].size();
+    }
+
+    RawDisplayList list {};
+    list.displays = displays.data();
+    list.count = displays.size();
+    sendResponse(messageApp, window, binding, RESPONSE_OK, &list);
+}
+
+// Frees a message copied by FaiscaMessageWindow, with the data it owns
+static void freeMessage(const AppMessage *msg) {
+    switch (msg->type) {
+        case APPMSG_SET_WINDOW_TITLE:
+            delete[] msg->windowTitle;
+            break;
+        case APPMSG_SET_CLIPBOARD_TEXT:
+            delete[] msg->clipboardText;
+            break;
+        case APPMSG_SET_WINDOW_ICON:
+            delete[] msg->windowIcon.pixels;
+            break;
+        case APPMSG_APP_PANICKED:
+            delete[] msg->panicMessage;
+            break;
+        case APPMSG_CUSTOM:
+            delete[] msg->custom.payload;
+            break;
+        default:
+            break;
+    }
+    delete msg;
+}
+
 extern "C" {
     uint32_t ECABI FaiscaMessageWindow(WindowInstance win, const AppMessage *msg) {
+        // Bounding the sides keeps the pixel length from wrapping around
+        if (msg->type == APPMSG_SET_WINDOW_ICON && (
+            msg->windowIcon.width == 0 || msg->windowIcon.width > MAX_WINDOW_ICON_SIDE ||
+            msg->windowIcon.height == 0 || msg->windowIcon.height > MAX_WINDOW_ICON_SIDE
+        )) {
+            std::cerr << "Rejecting a " << msg->windowIcon.width << "x" << msg->windowIcon.height
+                << " window icon" << std::endl;
+            return 0;
+        }
+
         AppMessage *ourMessage = new AppMessage;
         *ourMessage = *msg;
         switch (msg->type) {
@@ -35,6 +263,33 @@ extern "C" {
                 // We now point to our allocated string
                 ourMessage->windowTitle = ourString;
             } break;
+            case APPMSG_SET_CLIPBOARD_TEXT: {
+                // Same as the title, but the clipboard may hold any length
+                size_t strLength = strlen(msg->clipboardText) + 1;
+                char *ourString = new char[strLength];
+                SDL_strlcpy(ourString, msg->clipboardText, strLength);
+                ourMessage->clipboardText = ourString;
+            } break;
+            case APPMSG_SET_WINDOW_ICON: {
+                // Same as the title, the app frees the pixels once we return
+                size_t pixelsLength = size_t(msg->windowIcon.width) * msg->windowIcon.height * 4;
+                uint8_t *ourPixels = new uint8_t[pixelsLength];
+                memcpy(ourPixels, msg->windowIcon.pixels, pixelsLength);
+                ourMessage->windowIcon.pixels = ourPixels;
+            } break;
+            case APPMSG_APP_PANICKED: {
+                size_t strLength = strlen(msg->panicMessage) + 1;
+                char *ourString = new char[strLength];
+                SDL_strlcpy(ourString, msg->panicMessage, strLength);
+                ourMessage->panicMessage = ourString;
+            } break;
+            case APPMSG_CUSTOM: {
+                uint8_t *ourPayload = new uint8_t[msg->custom.len];
+                if (msg->custom.len > 0) {
+                    memcpy(ourPayload, msg->custom.payload, msg->custom.len);
+                }
+                ourMessage->custom.payload = ourPayload;
+            } break;
             default:
                 break;
         }
@@ -46,7 +301,9 @@ extern "C" {
         e.user.data1 = ourMessage;
         e.user.data2 = win;
 
-        if (SDL_PushEvent(&e) != 0) {
+        // SDL returns 1 once queued, 0 if an event filter dropped it and a
+        // negative value on errors, such as a full queue
+        if (SDL_PushEvent(&e) > 0) {
             // If pushed an event, notify to wake the event thread earlier.
             {
                 std::unique_lock<std::mutex> lk(gEventWaitMutex);
@@ -55,11 +312,101 @@ extern "C" {
             gEventWaitFence.notify_all();
             return 1;
         } else {
+            // The event thread will never see it, so we free it here
+            freeMessage(ourMessage);
             return 0;
         }
     }
 }
 
+struct LoadedApp {
+    std::unique_ptr<DyLib> lib;
+    FnRunApp runApp = nullptr;
+    FnMessageApp messageApp = nullptr;
+    FnSaveState saveState = nullptr;
+    FnLoadState loadState = nullptr;
+};
+
+// Loads the app library and does the handshake with it. When hot reloading
+// we load a copy instead, so that the original can be rebuilt while loaded.
+static bool loadApp(
+    const char *path,
+    bool hotReload,
+    SDL_Window *mainWindow,
+    const char **requiredExtensions,
+    unsigned int numExtensions,
+    LoadedApp &app
+) {
+    std::string libPath = path;
+    if (hotReload) {
+        libPath += ".hot";
+        std::error_code err;
+        std::filesystem::copy_file(
+            path, libPath, std::filesystem::copy_options::overwrite_existing, err);
+        if (err) {
+            std::cerr << "Failed to copy the app library to " << libPath << ": " << err.message() << std::endl;
+            return false;
+        }
+    }
+
+    app.lib = std::make_unique<DyLib>(libPath.c_str());
+    app.runApp = reinterpret_cast<FnRunApp>(app.lib->getProcAddr("faisca_run_app"));
+    app.messageApp = reinterpret_cast<FnMessageApp>(app.lib->getProcAddr("faisca_message_app"));
+    app.saveState = reinterpret_cast<FnSaveState>(app.lib->getProcAddr("faisca_save_state"));
+    app.loadState = reinterpret_cast<FnLoadState>(app.lib->getProcAddr("faisca_load_state"));
+    FnAbiVersion abiVersion = reinterpret_cast<FnAbiVersion>(app.lib->getProcAddr("faisca_abi_version"));
+
+    // We must not send a single message with a layout the app doesn't expect
+    if (abiVersion == nullptr) {
+        std::cerr << "The app library does not export faisca_abi_version, it is too old for this host" << std::endl;
+        return false;
+    }
+    uint32_t appAbiVersion = abiVersion();
+    if (appAbiVersion != ABI_VERSION) {
+        std::cerr << "The app library uses ABI version " << appAbiVersion
+            << ", but this host uses version " << ABI_VERSION
+            << ". Both must be built from the same Faisca version" << std::endl;
+        return false;
+    }
+
+    WindowMessage helloMsg = {};
+    helloMsg.type = WINMSG_HELLO;
+    helloMsg.hello.abiVersion = ABI_VERSION;
+    helloMsg.hello.capabilities =
+        CAPABILITY_MULTI_WINDOW | CAPABILITY_CLIPBOARD | CAPABILITY_CURSOR | CAPABILITY_GAMEPAD;
+    if (hotReload) {
+        helloMsg.hello.capabilities |= CAPABILITY_HOT_RELOAD;
+    }
+    uint32_t helloResult = app.messageApp(mainWindow, &helloMsg);
+    if (helloResult != MESSAGE_ACCEPTED) {
+        std::cerr << "The app rejected the handshake, code: " << helloResult << std::endl;
+        return false;
+    }
+
+    WindowMessage requiredExtensionMsg = {};
+    requiredExtensionMsg.type = WINMSG_VULKAN_INSTANCE_REQUIRED_EXTENSIONS;
+    requiredExtensionMsg.vk_instance_required_ext.names = requiredExtensions;
+    requiredExtensionMsg.vk_instance_required_ext.count = numExtensions;
+    app.messageApp(mainWindow, &requiredExtensionMsg);
+
+    return true;
+}
+
+static std::thread startApp(FnRunApp runApp, SDL_Window *mainWindow, std::atomic<bool> &appFinished) {
+    appFinished = false;
+    return std::thread([runApp, mainWindow, &appFinished]() {
+        runApp(mainWindow, FaiscaMessageWindow);
+        appFinished = true;
+    });
+}
+
+// The app hands us its state through this, we keep a copy until the new
+// library takes it
+static void ECABI collectState(void *user, const uint8_t *data, size_t len) {
+    std::vector<uint8_t> *state = static_cast<std::vector<uint8_t>*>(user);
+    state->assign(data, data + len);
+}
+
 int main(int argc, char *argv[]) {
     if (argc < 2) {
         std::cerr << "Missing faisca game shared object argument" << std::endl;
@@ -67,7 +414,7 @@ int main(int argc, char *argv[]) {
     }
     const char* sharedObjectFilepath = argv[1];
 
-    if (SDL_Init(SDL_INIT_VIDEO | SDL_INIT_EVENTS) < 0) {
+    if (SDL_Init(SDL_INIT_VIDEO | SDL_INIT_EVENTS | SDL_INIT_GAMECONTROLLER) < 0) {
         std::cerr << "Failed to initialize SDL2: " << SDL_GetError() << std::endl;
         return 1;
     }
@@ -75,7 +422,7 @@ int main(int argc, char *argv[]) {
     SDL_Window* mainWindow = SDL_CreateWindow(
         "Faisca Window",
         SDL_WINDOWPOS_UNDEFINED, SDL_WINDOWPOS_UNDEFINED, 800, 450,
-        SDL_WINDOW_SHOWN | SDL_WINDOW_VULKAN
+        SDL_WINDOW_SHOWN | SDL_WINDOW_VULKAN | SDL_WINDOW_ALLOW_HIGHDPI
     );
 
     if (mainWindow == nullptr) {
@@ -90,9 +437,8 @@ int main(int argc, char *argv[]) {
     }
     gUserEventNum = customEventType;
 
-    DyLib appLib(sharedObjectFilepath);
-    FnRunApp runApp = reinterpret_cast<FnRunApp>(appLib.getProcAddr("faisca_run_app"));
-    FnMessageApp messageApp = reinterpret_cast<FnMessageApp>(appLib.getProcAddr("faisca_message_app"));
+    // With hot reload, Ctrl+R reloads the app library, keeping the windows
+    bool hotReload = argc > 2 && strcmp(argv[2], "--hot-reload") == 0;
 
     unsigned int numExtensions = 0;
     if (!SDL_Vulkan_GetInstanceExtensions(mainWindow, &numExtensions, nullptr)) {
@@ -102,81 +448,276 @@ int main(int argc, char *argv[]) {
     const char **requiredExtensions = new const char*[numExtensions];
     SDL_Vulkan_GetInstanceExtensions(mainWindow, &numExtensions, requiredExtensions);
 
-    WindowMessage requiredExtensionMsg = {};
-    requiredExtensionMsg.type = WINMSG_VULKAN_INSTANCE_REQUIRED_EXTENSIONS;
-    requiredExtensionMsg.vk_instance_required_ext.names = requiredExtensions;
-    requiredExtensionMsg.vk_instance_required_ext.count = numExtensions;
-    messageApp(mainWindow, &requiredExtensionMsg);
+    LoadedApp app;
+    if (!loadApp(sharedObjectFilepath, hotReload, mainWindow, requiredExtensions, numExtensions, app)) {
+        return 1;
+    }
+    FnMessageApp messageApp = app.messageApp;
 
-    std::thread appFnThread(runApp, mainWindow, FaiscaMessageWindow);
+    std::atomic<bool> appFinished = false;
+    std::thread appFnThread = startApp(app.runApp, mainWindow, appFinished);
 
     void *backChannel= nullptr;
 
     SDL_Event e;
     bool running = true;
+    bool appPanicked = false;
+    bool reloading = false;
+    bool reloadFailed = false;
     while (running) {
         // int res = SDL_WaitEvent(&e);
-        if (SDL_PollEvent(&e) != 0) {
+        bool hadEvent = SDL_PollEvent(&e) != 0;
+        if (hadEvent) {
             switch (e.type) {
                 case SDL_QUIT: {
                     running = false;
                     WindowEvent windowEvent {};
                     windowEvent.type = WINEVT_QUIT;
 
-                    WindowMessage windowMessage {};
-                    windowMessage.type = WINMSG_WINDOW_EVENT;
-                    windowMessage.windowEvent.msgBackchannel = backChannel;
-                    windowMessage.windowEvent.windowEvent = &windowEvent;
-
-                    messageApp(mainWindow, &windowMessage);
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
                 } break;
                 case SDL_WINDOWEVENT:
                     switch (e.window.event) {
                         case SDL_WINDOWEVENT_RESIZED: {
+                            // data1 and data2 are the logical size, we also
+                            // need the pixel size
+                            SDL_Window *eWindow = SDL_GetWindowFromID(e.window.windowID);
+
+                            sendWindowResize(messageApp, backChannel, eWindow);
+                        } break;
+                        case SDL_WINDOWEVENT_DISPLAY_CHANGED: {
+                            // Moving to a screen with another DPI changes the
+                            // pixel size, without a resize event
+                            SDL_Window *eWindow = SDL_GetWindowFromID(e.window.windowID);
+                            auto found = gWindowScales.find(e.window.windowID);
+                            if (found == gWindowScales.end() || found->second != windowScale(eWindow)) {
+                                sendWindowResize(messageApp, backChannel, eWindow);
+                            }
+                        } break;
+                        case SDL_WINDOWEVENT_MOVED: {
                             WindowEvent windowEvent = WindowEvent {};
-                            windowEvent.type = WINEVT_WINDOW_RESIZE;
-                            windowEvent.windowResize.w = e.window.data1;
-                            windowEvent.windowResize.h = e.window.data2;
+                            windowEvent.type = WINEVT_MOVED;
+                            windowEvent.windowMoved.x = e.window.data1;
+                            windowEvent.windowMoved.y = e.window.data2;
 
-                            WindowMessage windowMessage {};
-                            windowMessage.type = WINMSG_WINDOW_EVENT;
-                            windowMessage.windowEvent.msgBackchannel = backChannel;
-                            windowMessage.windowEvent.windowEvent = &windowEvent;
+                            SDL_Window *eWindow = SDL_GetWindowFromID(e.window.windowID);
+
+                            sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
+                        } break;
+                        case SDL_WINDOWEVENT_FOCUS_GAINED:
+                        case SDL_WINDOWEVENT_FOCUS_LOST:
+                        case SDL_WINDOWEVENT_MINIMIZED:
+                        case SDL_WINDOWEVENT_RESTORED:
+                        case SDL_WINDOWEVENT_EXPOSED:
+                        case SDL_WINDOWEVENT_CLOSE: {
+                            // These events carry no data, only the type changes
+                            WindowEvent windowEvent = WindowEvent {};
+                            switch (e.window.event) {
+                                case SDL_WINDOWEVENT_FOCUS_GAINED:
+                                    windowEvent.type = WINEVT_FOCUS_GAINED;
+                                    break;
+                                case SDL_WINDOWEVENT_FOCUS_LOST:
+                                    windowEvent.type = WINEVT_FOCUS_LOST;
+                                    break;
+                                case SDL_WINDOWEVENT_MINIMIZED:
+                                    windowEvent.type = WINEVT_MINIMIZED;
+                                    break;
+                                case SDL_WINDOWEVENT_RESTORED:
+                                    windowEvent.type = WINEVT_RESTORED;
+                                    break;
+                                case SDL_WINDOWEVENT_CLOSE:
+                                    windowEvent.type = WINEVT_CLOSE_REQUESTED;
+                                    break;
+                                default:
+                                    windowEvent.type = WINEVT_EXPOSED;
+                                    break;
+                            }
 
                             SDL_Window *eWindow = SDL_GetWindowFromID(e.window.windowID);
 
-                            messageApp(eWindow, &windowMessage);
+                            sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
                         } break;
                     }
                     break;
+                case SDL_KEYDOWN:
+                case SDL_KEYUP: {
+                    if (hotReload && e.type == SDL_KEYDOWN && e.key.repeat == 0
+                            && e.key.keysym.sym == SDLK_r && (e.key.keysym.mod & KMOD_CTRL) != 0) {
+                        if (!reloading) {
+                            WindowEvent windowEvent {};
+                            windowEvent.type = WINEVT_RELOAD_REQUESTED;
+                            sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                            reloading = true;
+                        }
+                        break;
+                    }
+
+                    WindowEvent windowEvent {};
+                    windowEvent.type = e.type == SDL_KEYDOWN ? WINEVT_KEY_DOWN : WINEVT_KEY_UP;
+                    windowEvent.key.scancode = e.key.keysym.scancode;
+                    windowEvent.key.keycode = e.key.keysym.sym;
+                    windowEvent.key.modifiers = e.key.keysym.mod;
+                    windowEvent.key.repeat = e.key.repeat != 0 ? FAISCA_TRUE : FAISCA_FALSE;
+
+                    SDL_Window *eWindow = SDL_GetWindowFromID(e.key.windowID);
+
+                    sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
+                } break;
+                case SDL_MOUSEMOTION: {
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_MOUSE_MOTION;
+                    windowEvent.mouseMotion.x = e.motion.x;
+                    windowEvent.mouseMotion.y = e.motion.y;
+                    windowEvent.mouseMotion.xrel = e.motion.xrel;
+                    windowEvent.mouseMotion.yrel = e.motion.yrel;
+
+                    SDL_Window *eWindow = SDL_GetWindowFromID(e.motion.windowID);
+
+                    sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
+                } break;
+                case SDL_MOUSEBUTTONDOWN:
+                case SDL_MOUSEBUTTONUP: {
+                    // SDL button numbering is the same as ours
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_MOUSE_BUTTON;
+                    windowEvent.mouseButton.button = e.button.button;
+                    windowEvent.mouseButton.pressed =
+                        e.button.state == SDL_PRESSED ? FAISCA_TRUE : FAISCA_FALSE;
+                    windowEvent.mouseButton.clicks = e.button.clicks;
+                    windowEvent.mouseButton.x = e.button.x;
+                    windowEvent.mouseButton.y = e.button.y;
+
+                    SDL_Window *eWindow = SDL_GetWindowFromID(e.button.windowID);
+
+                    sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
+                } break;
+                case SDL_MOUSEWHEEL: {
+                    // We always report the scroll in the non-flipped direction
+                    float direction = e.wheel.direction == SDL_MOUSEWHEEL_FLIPPED ? -1.0f : 1.0f;
+
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_MOUSE_WHEEL;
+                    windowEvent.mouseWheel.x = e.wheel.preciseX * direction;
+                    windowEvent.mouseWheel.y = e.wheel.preciseY * direction;
+
+                    SDL_Window *eWindow = SDL_GetWindowFromID(e.wheel.windowID);
+
+                    sendWindowEvent(messageApp, backChannel, eWindow, windowEvent);
+                } break;
+                case SDL_DROPBEGIN:
+                case SDL_DROPFILE:
+                case SDL_DROPCOMPLETE: {
+                    WindowEvent windowEvent {};
+                    switch (e.type) {
+                        case SDL_DROPBEGIN:
+                            windowEvent.type = WINEVT_DROP_BEGIN;
+                            break;
+                        case SDL_DROPFILE:
+                            windowEvent.type = WINEVT_DROP_FILE;
+                            windowEvent.dropFile = e.drop.file;
+                            break;
+                        default:
+                            windowEvent.type = WINEVT_DROP_COMPLETE;
+                            break;
+                    }
+
+                    SDL_Window *eWindow = SDL_GetWindowFromID(e.drop.windowID);
+                    sendWindowEvent(messageApp, backChannel, eWindow != nullptr ? eWindow : mainWindow, windowEvent);
+
+                    // The app copied the path, so it is ours to free
+                    if (e.type == SDL_DROPFILE) {
+                        SDL_free(e.drop.file);
+                    }
+                } break;
+                // On Android, SDL destroys the native window once the app is in
+                // the background, and brings it back before it returns. We
+                // only have the main window there.
+                case SDL_APP_WILLENTERBACKGROUND:
+                case SDL_APP_DIDENTERBACKGROUND:
+                case SDL_APP_WILLENTERFOREGROUND:
+                case SDL_APP_DIDENTERFOREGROUND: {
+                    WindowEvent windowEvent {};
+                    switch (e.type) {
+                        case SDL_APP_WILLENTERBACKGROUND:
+                            windowEvent.type = WINEVT_APP_WILL_ENTER_BACKGROUND;
+                            break;
+                        case SDL_APP_DIDENTERBACKGROUND:
+                            windowEvent.type = WINEVT_SURFACE_LOST;
+                            break;
+                        case SDL_APP_WILLENTERFOREGROUND:
+                            windowEvent.type = WINEVT_SURFACE_RECREATED;
+                            break;
+                        default:
+                            windowEvent.type = WINEVT_APP_DID_ENTER_FOREGROUND;
+                            break;
+                    }
+
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                } break;
+                // Controllers are not tied to any window, so we report them
+                // through the main one
+                case SDL_CONTROLLERDEVICEADDED: {
+                    // Here `which` is the device index, not the instance id
+                    SDL_GameController *controller = SDL_GameControllerOpen(e.cdevice.which);
+                    if (controller == nullptr) {
+                        std::cerr << "Failed to open game controller: " << SDL_GetError() << std::endl;
+                        break;
+                    }
+                    SDL_JoystickID id = SDL_JoystickInstanceID(SDL_GameControllerGetJoystick(controller));
+                    gControllers[id] = controller;
+
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_CONTROLLER_ADDED;
+                    windowEvent.controllerDevice.id = id;
+
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                } break;
+                case SDL_CONTROLLERDEVICEREMOVED: {
+                    auto found = gControllers.find(e.cdevice.which);
+                    if (found != gControllers.end()) {
+                        SDL_GameControllerClose(found->second);
+                        gControllers.erase(found);
+                    }
+
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_CONTROLLER_REMOVED;
+                    windowEvent.controllerDevice.id = e.cdevice.which;
+
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                } break;
+                case SDL_CONTROLLERBUTTONDOWN:
+                case SDL_CONTROLLERBUTTONUP: {
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_CONTROLLER_BUTTON;
+                    windowEvent.controllerButton.id = e.cbutton.which;
+                    windowEvent.controllerButton.button = e.cbutton.button;
+                    windowEvent.controllerButton.pressed = e.cbutton.state == SDL_PRESSED ? FAISCA_TRUE : FAISCA_FALSE;
+
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                } break;
+                case SDL_CONTROLLERAXISMOTION: {
+                    WindowEvent windowEvent {};
+                    windowEvent.type = WINEVT_CONTROLLER_AXIS;
+                    windowEvent.controllerAxis.id = e.caxis.which;
+                    windowEvent.controllerAxis.axis = e.caxis.axis;
+                    windowEvent.controllerAxis.value = e.caxis.value;
+
+                    sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+                } break;
                 case SDL_USEREVENT: {
                     const AppMessage *msg = static_cast<const AppMessage*>(e.user.data1);
                     SDL_Window *msgWindow = static_cast<SDL_Window*>(e.user.data2);
                     switch (msg->type) {
                         case APPMSG_SET_WINDOW_SIZE: {
                             SDL_SetWindowSize(msgWindow, msg->windowSize.width, msg->windowSize.height);
-                            WindowEvent windowEvent = WindowEvent {};
-                            windowEvent.type = WINEVT_WINDOW_RESIZE;
-                            windowEvent.windowResize.w = msg->windowSize.width;
-                            windowEvent.windowResize.h = msg->windowSize.height;
-
-                            WindowMessage windowMessage {};
-                            windowMessage.type = WINMSG_WINDOW_EVENT;
-                            windowMessage.windowEvent.msgBackchannel = backChannel;
-                            windowMessage.windowEvent.windowEvent = &windowEvent;
-
-                            SDL_Window *eWindow = SDL_GetWindowFromID(e.window.windowID);
-
-                            messageApp(eWindow, &windowMessage);
+                            sendWindowResize(messageApp, backChannel, msgWindow);
                         } break;
                         case APPMSG_SET_FULLSCREEN:
-                            SDL_SetWindowFullscreen(
+                            setFullscreen(
                                 msgWindow,
-                                (msg->fullscreen == FULLSCREEN_NONE ?
-                                    0 : (msg->fullscreen == FULLSCREEN_REAL ?
-                                        SDL_WINDOW_FULLSCREEN : SDL_WINDOW_FULLSCREEN_DESKTOP
-                                    )
-                                )
+                                msg->fullscreen.type,
+                                msg->fullscreen.display,
+                                msg->fullscreen.mode
                             );
                             break;
                         case APPMSG_SET_BORDERLESS:
@@ -189,34 +730,151 @@ int main(int argc, char *argv[]) {
                         case APPMSG_SET_WINDOW_RESIZABLE:
                             SDL_SetWindowResizable(msgWindow, msg->windowResizable != 0 ? SDL_TRUE : SDL_FALSE);
                             break;
+                        case APPMSG_SET_CLIPBOARD_TEXT:
+                            if (SDL_SetClipboardText(msg->clipboardText) != 0) {
+                                std::cerr << "Failed to set clipboard text: " << SDL_GetError() << std::endl;
+                            }
+                            delete[] msg->clipboardText;
+                            break;
+                        case APPMSG_SET_CURSOR_VISIBLE:
+                            SDL_ShowCursor(msg->cursorVisible != 0 ? SDL_ENABLE : SDL_DISABLE);
+                            break;
+                        case APPMSG_SET_RELATIVE_MOUSE_MODE:
+                            if (SDL_SetRelativeMouseMode(msg->relativeMouseMode != 0 ? SDL_TRUE : SDL_FALSE) != 0) {
+                                std::cerr << "Failed to set relative mouse mode: " << SDL_GetError() << std::endl;
+                            }
+                            break;
+                        case APPMSG_SET_SYSTEM_CURSOR:
+                            setSystemCursor(msg->systemCursor);
+                            break;
+                        case APPMSG_WARP_MOUSE:
+                            SDL_WarpMouseInWindow(msgWindow, msg->warpMouse.x, msg->warpMouse.y);
+                            break;
+                        case APPMSG_SET_WINDOW_ICON: {
+                            SDL_Surface *icon = SDL_CreateRGBSurfaceWithFormatFrom(
+                                const_cast<uint8_t*>(msg->windowIcon.pixels),
+                                msg->windowIcon.width, msg->windowIcon.height,
+                                32, msg->windowIcon.width * 4, SDL_PIXELFORMAT_RGBA32
+                            );
+                            if (icon == nullptr) {
+                                std::cerr << "Failed to create window icon: " << SDL_GetError() << std::endl;
+                            } else {
+                                // SDL copies the pixels
+                                SDL_SetWindowIcon(msgWindow, icon);
+                                SDL_FreeSurface(icon);
+                            }
+                            delete[] msg->windowIcon.pixels;
+                        } break;
+                        case APPMSG_RUMBLE_CONTROLLER: {
+                            auto found = gControllers.find(msg->rumbleController.id);
+                            if (found == gControllers.end()) {
+                                std::cerr << "Cannot rumble unknown controller: " << msg->rumbleController.id << std::endl;
+                            } else if (SDL_GameControllerRumble(
+                                found->second,
+                                msg->rumbleController.low,
+                                msg->rumbleController.high,
+                                msg->rumbleController.durationMs
+                            ) != 0) {
+                                std::cerr << "Failed to rumble controller: " << SDL_GetError() << std::endl;
+                            }
+                        } break;
 
 
                         case APPMSG_CREATE_VULKAN_SURFACE: {
-                            SDL_Vulkan_CreateSurface(
+                            VkSurfaceKHR surface = VK_NULL_HANDLE;
+                            SDL_bool created = SDL_Vulkan_CreateSurface(
                                 msgWindow,
                                 (VkInstance) msg->windowSurfaceCreateInfo.instance_handle,
-                                (VkSurfaceKHR*) msg->windowSurfaceCreateInfo.responseBinding->out
+                                &surface
                             );
-                            WindowMessage message = {};
-                            message.type = WINMSG_RESPONSE_NOTIFY;
-                            message.responseNotifyBinding = msg->windowSurfaceCreateInfo.responseBinding;
-                            messageApp(msgWindow, &message);
+                            if (created) {
+                                sendResponse(
+                                    messageApp, msgWindow, msg->windowSurfaceCreateInfo.responseBinding,
+                                    RESPONSE_OK, &surface
+                                );
+                            } else {
+                                std::cerr << "Failed to create Vulkan surface: " << SDL_GetError() << std::endl;
+                                sendResponse(
+                                    messageApp, msgWindow, msg->windowSurfaceCreateInfo.responseBinding,
+                                    RESPONSE_FAILED, nullptr
+                                );
+                            }
                         } break;
                         case APPMSG_QUERY_VIEWPORT_EXTENT: {
-                            Extent2D *vkExtent2D = (Extent2D*) msg->queryResponseBinding->out;
                             int width, height;
                             SDL_GetWindowSizeInPixels(msgWindow, &width, &height);
-                            vkExtent2D->width = width;
-                            vkExtent2D->height = height;
+                            Extent2D extent {};
+                            extent.width = width;
+                            extent.height = height;
 
-                            WindowMessage message = {};
-                            message.type = WINMSG_RESPONSE_NOTIFY;
-                            message.responseNotifyBinding = msg->queryResponseBinding;
-                            messageApp(msgWindow, &message);
+                            sendResponse(messageApp, msgWindow, msg->queryResponseBinding, RESPONSE_OK, &extent);
                         } break;
                         case APPMSG_SET_MSG_BACKCHANNEL:
                             backChannel = msg->msgBackchannel;
                             break;
+                        case APPMSG_CREATE_WINDOW: {
+                            SDL_Window *newWindow = SDL_CreateWindow(
+                                "Faisca Window",
+                                SDL_WINDOWPOS_UNDEFINED, SDL_WINDOWPOS_UNDEFINED,
+                                msg->createWindow.width, msg->createWindow.height,
+                                SDL_WINDOW_SHOWN | SDL_WINDOW_VULKAN | SDL_WINDOW_ALLOW_HIGHDPI
+                            );
+                            if (newWindow != nullptr) {
+                                sendResponse(
+                                    messageApp, msgWindow, msg->createWindow.responseBinding,
+                                    RESPONSE_OK, &newWindow
+                                );
+                            } else {
+                                std::cerr << "Failed to create SDL window: " << SDL_GetError() << std::endl;
+                                sendResponse(
+                                    messageApp, msgWindow, msg->createWindow.responseBinding,
+                                    RESPONSE_FAILED, nullptr
+                                );
+                            }
+                        } break;
+                        case APPMSG_QUERY_CLIPBOARD_TEXT: {
+                            // SDL gives us an empty string if it fails
+                            char *text = SDL_GetClipboardText();
+                            sendResponse(messageApp, msgWindow, msg->queryResponseBinding, RESPONSE_OK, text);
+                            SDL_free(text);
+                        } break;
+                        case APPMSG_QUERY_DISPLAYS:
+                            sendDisplays(messageApp, msgWindow, msg->queryResponseBinding);
+                            break;
+                        case APPMSG_QUERY_DISPLAY_SCALE: {
+                            float scale = windowScale(msgWindow);
+                            sendResponse(messageApp, msgWindow, msg->queryResponseBinding, RESPONSE_OK, &scale);
+                        } break;
+                        case APPMSG_DESTROY_WINDOW:
+                            // The main window lives as long as the host does
+                            if (msgWindow != mainWindow) {
+                                gWindowScales.erase(SDL_GetWindowID(msgWindow));
+                                SDL_DestroyWindow(msgWindow);
+                            }
+                            break;
+                        case APPMSG_APP_PANICKED: {
+                            std::cerr << "The app panicked: " << msg->panicMessage << std::endl;
+                            delete[] msg->panicMessage;
+
+                            // The app thread may still be running, if the
+                            // panic happened while handling one of our
+                            // messages, so we ask it to quit before joining it
+                            WindowEvent windowEvent {};
+                            windowEvent.type = WINEVT_QUIT;
+                            sendWindowEvent(messageApp, backChannel, mainWindow, windowEvent);
+
+                            running = false;
+                            appPanicked = true;
+                        } break;
+                        case APPMSG_CUSTOM: {
+                            auto handler = gCustomHandlers.find(msg->custom.kind);
+                            if (handler != gCustomHandlers.end()) {
+                                handler->second(messageApp, msgWindow, msg->custom.payload, msg->custom.len);
+                            } else {
+                                std::cerr << "No plugin handles custom messages of kind " << msg->custom.kind << std::endl;
+                            }
+                            delete[] msg->custom.payload;
+                        } break;
                         default:
                             break;
                     }
@@ -225,6 +883,37 @@ int main(int argc, char *argv[]) {
             }
         } // End of SDL_PollEvent
 
+        // Only swap the library once the app returned and every message it
+        // sent is handled, as those may point into its memory
+        if (reloading && running && appFinished && !hadEvent) {
+            appFnThread.join();
+
+            std::vector<uint8_t> state;
+            if (app.saveState != nullptr && app.saveState(collectState, &state) != STATE_OK) {
+                std::cerr << "The app failed to save its state, reloading without it" << std::endl;
+                state.clear();
+            }
+
+            // The back-channel belongs to the old library, the new one will
+            // send us its own
+            backChannel = nullptr;
+            app = LoadedApp{};
+            if (!loadApp(sharedObjectFilepath, hotReload, mainWindow, requiredExtensions, numExtensions, app)) {
+                reloadFailed = true;
+                break;
+            }
+            messageApp = app.messageApp;
+
+            if (!state.empty() && app.loadState != nullptr
+                    && app.loadState(state.data(), state.size()) != STATE_OK) {
+                std::cerr << "The app failed to load its state" << std::endl;
+            }
+
+            appFnThread = startApp(app.runApp, mainWindow, appFinished);
+            reloading = false;
+            continue;
+        }
+
         {
             std::unique_lock<std::mutex> lk(gEventWaitMutex);
             gEventWaitFence.wait_for(lk, 8ms, []{return gEventWaitFlag;});
@@ -232,10 +921,23 @@ int main(int argc, char *argv[]) {
         }
     }
 
-    appFnThread.join();
+    if (appFnThread.joinable()) {
+        appFnThread.join();
+    }
+
+    for (auto &controller : gControllers) {
+        SDL_GameControllerClose(controller.second);
+    }
+    gControllers.clear();
+
+    for (SDL_Cursor *cursor : gSystemCursors) {
+        if (cursor != nullptr) {
+            SDL_FreeCursor(cursor);
+        }
+    }
 
     SDL_DestroyWindow(mainWindow);
     SDL_Quit();
 
-    return 0;
+    return appPanicked || reloadFailed ? 1 : 0;
 }